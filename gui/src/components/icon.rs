@@ -23,6 +23,9 @@ pub enum Icon {
     AlertCircleOutline,
     PlayOutline,
     FolderOpenOutline,
+    WrenchOutline,
+    Pause,
+    Close,
     Github,
     Minecraft,
     Modrinth,
@@ -65,6 +68,9 @@ impl Icon {
             Icon::FolderOpenOutline => {
                 include_bytes!("../../../assets/mdi/folder-open-outline.svg")
             }
+            Icon::WrenchOutline => include_bytes!("../../../assets/mdi/wrench-outline.svg"),
+            Icon::Pause => include_bytes!("../../../assets/mdi/pause.svg"),
+            Icon::Close => include_bytes!("../../../assets/mdi/close.svg"),
             Icon::Github => include_bytes!("../../../assets/simple-icons/github.svg"),
             Icon::Minecraft => include_bytes!("../../../assets/simple-icons/minecraft.svg"),
             Icon::Modrinth => include_bytes!("../../../assets/simple-icons/modrinth.svg"),
@@ -72,6 +72,12 @@ pub fn view<'a>(
             "New Instance",
         ))
         .push(vertical_space(Length::Fill))
+        .push(change_view_button(
+            Page::Java,
+            current_page,
+            Icon::PackageVariant.view(32),
+            "Java",
+        ))
         .push(change_view_button(
             Page::Accounts,
             current_page,
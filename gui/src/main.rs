@@ -3,6 +3,8 @@
 
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+use std::{env, path::PathBuf, process};
+
 use iced::{executor, theme, Application, Color, Command, Element, Settings, Subscription, Theme};
 
 use crate::types::launcher::Launcher;
@@ -16,7 +18,44 @@ mod types;
 
 pub const LOGO_PNG: &[u8] = include_bytes!("../../assets/logo-128x128.png");
 
+const USAGE: &str = "Usage: crab-launcher [--data-dir <dir>] [--migrate-to <dir>]";
+
 pub fn main() -> iced::Result {
+    let mut args = env::args().skip(1);
+    let mut migrate_to = None;
+
+    while let Some(arg) = args.next() {
+        match (arg.as_str(), args.next()) {
+            ("--data-dir", Some(dir)) => {
+                if !lib::paths::set_base_dir(PathBuf::from(&dir)) {
+                    eprintln!("Can't use {} as the data directory, it's already set", dir);
+                    process::exit(2);
+                }
+            }
+            ("--migrate-to", Some(dir)) => {
+                migrate_to = Some(PathBuf::from(dir));
+            }
+            _ => {
+                eprintln!("{}", USAGE);
+                process::exit(2);
+            }
+        }
+    }
+
+    // moves the data and exits, the launcher isn't started
+    if let Some(dest) = migrate_to {
+        match lib::paths::migrate(&dest) {
+            Ok(()) => {
+                println!("Data moved to {}", dest.display());
+                process::exit(0);
+            }
+            Err(error) => {
+                eprintln!("Failed to move the data: {}", error);
+                process::exit(1);
+            }
+        }
+    }
+
     let mut settings = Settings::default();
     let icon = iced::window::icon::from_file_data(LOGO_PNG, None).unwrap();
     settings.window.icon = Some(icon);
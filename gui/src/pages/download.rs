@@ -1,36 +1,173 @@
 // SPDX-FileCopyrightText: 2023 Manuel Quarneti <manuelquarneti@protonmail.com>
 // SPDX-License-Identifier: GPL-3.0-only
 
+use std::time::Duration;
+
 use iced::{
-    Alignment,
-    Element, Length, widget::{Column, progress_bar, text, vertical_space},
+    theme,
+    widget::{
+        button, container, horizontal_space, progress_bar, scrollable, text, vertical_space,
+        Column, Row,
+    },
+    Alignment, Element, Length,
 };
+use lib::space::format_size;
+use lib::tasks::TaskState;
+use lib::DownloadProgress;
 
-use crate::types::download::{Download, State};
+use crate::components::icon::Icon;
+use crate::style;
+use crate::types::download::{Download, State, Task};
 use crate::types::messages::Message;
 
+fn format_eta(eta: Duration) -> String {
+    let secs = eta.as_secs();
+
+    if secs >= 60 {
+        format!("{}m {}s", secs / 60, secs % 60)
+    } else {
+        format!("{secs}s")
+    }
+}
+
+fn details(progress: &DownloadProgress) -> String {
+    let mut details = format!(
+        "{} / {} ({} files of {})",
+        format_size(progress.bytes_downloaded),
+        format_size(progress.bytes_total),
+        progress.items_completed,
+        progress.items_total,
+    );
+
+    details.push_str(&format!(
+        " - {}/s",
+        format_size(progress.bytes_per_second as u64)
+    ));
+
+    if let Some(eta) = progress.eta {
+        details.push_str(&format!(" - {} left", format_eta(eta)));
+    }
+
+    details
+}
+
+fn task_status(task: &Task) -> String {
+    match &task.state {
+        TaskState::Pending => "Waiting".to_string(),
+        TaskState::Running => task
+            .progress
+            .as_ref()
+            .map_or("Running".to_string(), details),
+        TaskState::Done => "Done".to_string(),
+        TaskState::Failed(error) => format!("Failed: {error}"),
+        TaskState::Skipped => "Skipped".to_string(),
+        TaskState::Cancelled => "Cancelled".to_string(),
+    }
+}
+
 pub fn view(download: &Download) -> Element<Message> {
     let current_progress = match &download.state {
         State::Idle { .. } => 0.0,
-        State::Downloading { progress, queue: _ } => *progress,
+        State::Downloading { .. } => download.percentage(),
         State::Finished { .. } => 100.0,
-        State::Errored { .. } => 0.0,
+        State::Cancelled | State::Errored { .. } => 0.0,
     };
 
-    let progress_bar = progress_bar(0.0..=100.0, current_progress);
+    let overall_progress = progress_bar(0.0..=100.0, current_progress);
 
     let current_progress = format!("Downloading... {current_progress:.2}%");
-    let text = text(match &download.state {
+    let text_content = text(match &download.state {
         State::Idle => "Starting download",
         State::Finished => "Download finished!",
+        State::Cancelled => "Download cancelled",
+        State::Downloading { paused: true, .. } => "Paused",
         State::Downloading { .. } => &current_progress,
-        State::Errored => "Something went wrong :(",
+        State::Errored { .. } => "Something went wrong :(",
     });
 
-    Column::new()
+    let mut column = Column::new()
         .push(vertical_space(Length::Fill))
-        .push(text)
-        .push(progress_bar)
+        .push(text_content)
+        .push(overall_progress);
+
+    if !download.tasks.is_empty() {
+        let mut tasks = Column::new().spacing(5);
+
+        for task in &download.tasks {
+            let header = Row::new()
+                .push(text(&task.name))
+                .push(horizontal_space(Length::Fill))
+                .push(text(task_status(task)).size(14));
+
+            tasks = tasks
+                .push(header)
+                .push(progress_bar(0.0..=100.0, task.percentage()).height(Length::Fixed(8.)));
+        }
+
+        column = column.push(container(tasks).padding(10).style(style::card()));
+    }
+
+    if let State::Downloading { paused, .. } = &download.state {
+        let pause_button = if *paused {
+            button(Icon::PlayOutline.view(24)).on_press(Message::ResumeDownload)
+        } else {
+            button(Icon::Pause.view(24)).on_press(Message::PauseDownload)
+        };
+
+        let controls = Row::new()
+            .push(pause_button.style(style::circle_button(theme::Button::Secondary)))
+            .push(
+                button(Icon::Close.view(24))
+                    .on_press(Message::CancelDownload)
+                    .style(style::circle_button(theme::Button::Destructive)),
+            )
+            .spacing(5);
+
+        column = column.push(controls);
+    }
+
+    if let State::Errored { .. } = &download.state {
+        let retry_button = button(text(" Retry ").size(20))
+            .on_press(Message::RetryDownload)
+            .style(style::circle_button(theme::Button::Primary));
+
+        column = column.push(retry_button);
+    }
+
+    if let Some(report) = &download.repair_report {
+        let failed = match &download.state {
+            State::Errored { failed, .. } => failed.len(),
+            _ => 0,
+        };
+
+        if let State::Finished { .. } | State::Errored { .. } = &download.state {
+            column = column.push(text(format!(
+                "Checked {} files: {} missing, {} corrupted, {} repaired",
+                report.checked,
+                report.missing.len(),
+                report.corrupted.len(),
+                report.broken().saturating_sub(failed),
+            )));
+        }
+    }
+
+    if let State::Errored { failed, .. } = &download.state {
+        if !failed.is_empty() {
+            let mut list = Column::new().spacing(5);
+            for error in failed {
+                list = list.push(text(error).size(14));
+            }
+
+            column = column
+                .push(text(format!(
+                    "{} files could not be downloaded:",
+                    failed.len()
+                )))
+                .push(scrollable(list).height(Length::Fixed(200.)));
+        }
+    }
+
+    column
         .push(vertical_space(Length::Fill))
         .spacing(10)
         .padding(10)
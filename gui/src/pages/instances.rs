@@ -32,6 +32,11 @@ pub fn view(instances: &Instances) -> Element<Message> {
                     .on_press(Message::OpenInstanceConfig(name.clone()))
                     .style(style::circle_button(theme::Button::Secondary)),
             )
+            .push(
+                button(Icon::WrenchOutline.view(24))
+                    .on_press(Message::RepairInstance(name.clone()))
+                    .style(style::circle_button(theme::Button::Secondary)),
+            )
             .push(
                 button(Icon::DeleteOutline.view(24))
                     .on_press(Message::DeleteInstance(name.clone()))
@@ -0,0 +1,219 @@
+// SPDX-FileCopyrightText: 2023 Manuel Quarneti <manuelquarneti@protonmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::fmt;
+
+use iced::{
+    theme,
+    widget::{button, container, horizontal_space, pick_list, scrollable, text, Column, Row},
+    Alignment, Element, Length,
+};
+use lib::runtimes::{RuntimeOverview, RuntimeUpdate};
+
+use crate::components::icon::Icon;
+use crate::style;
+use crate::types::java::Java;
+use crate::types::messages::Message;
+
+/// What an instance runs on, as picked on this page.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Pin {
+    Newest,
+    Release(String),
+}
+
+impl fmt::Display for Pin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Pin::Newest => write!(f, "Newest installed"),
+            Pin::Release(release) => write!(f, "{}", release),
+        }
+    }
+}
+
+fn runtimes_view(overview: &RuntimeOverview) -> Element<Message> {
+    let mut col = Column::new().spacing(10);
+
+    if overview.runtimes.is_empty() {
+        col = col.push(text("No Java runtimes installed"));
+    }
+
+    for runtime in &overview.runtimes {
+        let users = overview.get_users(runtime);
+        let unused = users.is_empty();
+        let users = if unused {
+            "Unused".to_string()
+        } else {
+            format!("Used by {}", users.join(", "))
+        };
+
+        let info = Column::new()
+            .push(text(format!(
+                "Java {} ({})",
+                runtime.major, runtime.release
+            )))
+            .push(text(format!(
+                "{} {}, {}",
+                runtime.vendor, runtime.version, runtime.arch
+            )))
+            .push(text(users));
+
+        let mut row = Row::new()
+            .push(info)
+            .push(horizontal_space(Length::Fill))
+            .align_items(Alignment::Center)
+            .padding(10);
+
+        // deleting a runtime in use would only have it downloaded again
+        if unused {
+            row = row.push(
+                button(Icon::DeleteOutline.view(24))
+                    .on_press(Message::DeleteRuntime(runtime.clone()))
+                    .style(style::circle_button(theme::Button::Destructive)),
+            );
+        }
+
+        col = col.push(container(row).style(style::card()));
+    }
+
+    col.into()
+}
+
+fn instances_view(overview: &RuntimeOverview) -> Element<Message> {
+    let mut col = Column::new().spacing(10);
+
+    for instance in &overview.instances {
+        let mut pins = vec![Pin::Newest];
+        pins.extend(
+            overview
+                .get_releases(instance.major)
+                .into_iter()
+                .map(|release| Pin::Release(release.to_string())),
+        );
+
+        let selected = match &instance.pinned {
+            Some(release) => Pin::Release(release.clone()),
+            None => Pin::Newest,
+        };
+
+        let status = if instance.dir.is_some() {
+            format!("Java {}", instance.major)
+        } else {
+            format!("Java {}, not installed", instance.major)
+        };
+
+        let name = instance.instance.clone();
+        let row = Row::new()
+            .push(text(&instance.instance))
+            .push(horizontal_space(Length::Fill))
+            .push(text(status))
+            .push(pick_list(pins, Some(selected), move |pin| {
+                let release = match pin {
+                    Pin::Newest => None,
+                    Pin::Release(release) => Some(release),
+                };
+
+                Message::PinRuntime(name.clone(), release)
+            }))
+            .align_items(Alignment::Center)
+            .spacing(10)
+            .padding(10);
+
+        col = col.push(container(row).style(style::card()));
+    }
+
+    col.into()
+}
+
+fn updates_view(updates: &[RuntimeUpdate]) -> Element<Message> {
+    let mut col = Column::new().spacing(10);
+
+    if updates.is_empty() {
+        col = col.push(text("Every runtime is up to date"));
+    }
+
+    for update in updates {
+        let row = Row::new()
+            .push(text(format!(
+                "Java {}: {} → {}",
+                update.major, update.installed, update.latest
+            )))
+            .push(horizontal_space(Length::Fill))
+            .push(
+                button(Icon::DownloadOutline.view(24))
+                    .on_press(Message::UpdateRuntime(update.major))
+                    .style(style::circle_button(theme::Button::Primary)),
+            )
+            .align_items(Alignment::Center)
+            .padding(10);
+
+        col = col.push(container(row).style(style::card()));
+    }
+
+    col.into()
+}
+
+pub fn view(java: &Java) -> Element<Message> {
+    let Some(overview) = &java.overview else {
+        return Column::new()
+            .push(text("Java").size(30))
+            .push(text("Loading..."))
+            .spacing(10)
+            .padding(10)
+            .into();
+    };
+
+    let mut content = Column::new()
+        .push(text("Runtimes").size(20))
+        .push(runtimes_view(overview))
+        .spacing(10);
+
+    if !overview.instances.is_empty() {
+        content = content
+            .push(text("Instances").size(20))
+            .push(instances_view(overview));
+    }
+
+    if let Some(updates) = &java.updates {
+        content = content
+            .push(text("Updates").size(20))
+            .push(updates_view(updates));
+    }
+
+    let check_updates_button = button(
+        Row::new()
+            .push(text(" Check for updates "))
+            .push(Icon::DownloadOutline.view(24))
+            .padding(5)
+            .align_items(Alignment::Center),
+    )
+    .style(style::circle_button(theme::Button::Primary))
+    .on_press(Message::CheckRuntimeUpdates);
+
+    let mut remove_unused_button = button(
+        Row::new()
+            .push(text(" Remove unused "))
+            .push(Icon::DeleteOutline.view(24))
+            .padding(5)
+            .align_items(Alignment::Center),
+    )
+    .style(style::circle_button(theme::Button::Destructive));
+
+    if !overview.get_unused().is_empty() {
+        remove_unused_button = remove_unused_button.on_press(Message::RemoveUnusedRuntimes);
+    }
+
+    let footer = Row::new()
+        .push(horizontal_space(Length::Fill))
+        .push(remove_unused_button)
+        .push(check_updates_button)
+        .spacing(10);
+
+    Column::new()
+        .push(text("Java").size(30))
+        .push(scrollable(content).height(Length::Fill))
+        .push(footer)
+        .spacing(10)
+        .padding(10)
+        .into()
+}
@@ -7,6 +7,7 @@ mod adding_offline_account;
 mod download;
 mod error;
 mod instances;
+mod java;
 mod login;
 mod modrinth_modpacks;
 mod new_instance;
@@ -24,6 +25,7 @@ pub enum Page {
     NewInstance,
     VanillaInstaller,
     Settings,
+    Java,
     About,
     Accounts,
     AddingAccount,
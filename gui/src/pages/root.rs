@@ -24,7 +24,12 @@ pub fn view(launcher: &Launcher) -> Element<Message> {
             pages::adding_offline_account::view(&launcher.offline_account_username)
         }
         Page::VanillaInstaller => pages::vanilla_installer::view(&launcher.vanilla_installer),
-        Page::Settings => pages::settings::view(&launcher.settings),
+        Page::Settings => pages::settings::view(
+            &launcher.settings,
+            launcher.garbage.as_ref(),
+            launcher.freed_space,
+        ),
+        Page::Java => pages::java::view(&launcher.java),
         Page::Download => pages::download::view(&launcher.download),
         Page::ModrinthModpacks => pages::modrinth_modpacks::view(&launcher.modrinth_modpacks),
     };
@@ -3,15 +3,74 @@
 
 use iced::{
     theme,
-    widget::{button, container, horizontal_space, text, toggler, vertical_space, Column, Row},
+    widget::{
+        button, container, horizontal_space, text, text_input, toggler, vertical_space, Column, Row,
+    },
     Alignment, Element, Length,
 };
+use lib::gc::GarbageReport;
 use lib::settings::Settings;
+use lib::space::format_size;
 
 use crate::types::messages::Message;
 use crate::{components::icon::Icon, style};
 
-pub fn view(settings: &Settings) -> Element<Message> {
+fn format_speed(speed: Option<u64>) -> String {
+    speed.map(|speed| speed.to_string()).unwrap_or_default()
+}
+
+fn storage_view(garbage: Option<&GarbageReport>, freed_space: Option<u64>) -> Element<Message> {
+    let mut col = Column::new()
+        .push(text("Unused libraries, assets and Java runtimes"))
+        .spacing(10);
+
+    if let Some(freed_space) = freed_space {
+        col = col.push(text(format!("Freed {}", format_size(freed_space))));
+    }
+
+    let button = match garbage {
+        Some(report) if !report.paths.is_empty() => {
+            col = col.push(text(format!(
+                "{} files, {}",
+                report.paths.len(),
+                format_size(report.size)
+            )));
+
+            button(
+                Row::new()
+                    .push(text(" Delete "))
+                    .push(Icon::DeleteOutline.view(24))
+                    .padding(5)
+                    .align_items(Alignment::Center),
+            )
+            .style(style::circle_button(theme::Button::Destructive))
+            .on_press(Message::CollectGarbage)
+        }
+        _ => {
+            if garbage.is_some() {
+                col = col.push(text("Nothing to clean up"));
+            }
+
+            button(
+                Row::new()
+                    .push(text(" Find unused files "))
+                    .push(Icon::WrenchOutline.view(24))
+                    .padding(5)
+                    .align_items(Alignment::Center),
+            )
+            .style(style::circle_button(theme::Button::Primary))
+            .on_press(Message::FindGarbage)
+        }
+    };
+
+    col.push(button).into()
+}
+
+pub fn view<'a>(
+    settings: &'a Settings,
+    garbage: Option<&'a GarbageReport>,
+    freed_space: Option<u64>,
+) -> Element<'a, Message> {
     let mut col = Column::new().padding(10);
 
     #[cfg(feature = "updater")]
@@ -25,6 +84,30 @@ pub fn view(settings: &Settings) -> Element<Message> {
         col = col.push(check_for_updates);
     }
 
+    let offline = toggler(
+        "Offline mode".to_owned(),
+        settings.offline,
+        Message::SetOffline,
+    );
+
+    col = col.push(offline);
+
+    let max_download_speed = text_input("Unlimited", &format_speed(settings.max_download_speed))
+        .on_input(Message::SetMaxDownloadSpeed);
+
+    let max_background_download_speed = text_input(
+        "Same as above",
+        &format_speed(settings.max_background_download_speed),
+    )
+    .on_input(Message::SetMaxBackgroundDownloadSpeed);
+
+    col = col
+        .push(text("Download speed limit (KB/s)"))
+        .push(max_download_speed)
+        .push(text("Download speed limit while playing (KB/s)"))
+        .push(max_background_download_speed)
+        .spacing(10);
+
     let save_button = button(
         Row::new()
             .push(text(" Save "))
@@ -38,6 +121,8 @@ pub fn view(settings: &Settings) -> Element<Message> {
     Column::new()
         .push(text("Settings").size(30))
         .push(container(col).style(style::card()))
+        .push(text("Storage").size(20))
+        .push(container(storage_view(garbage, freed_space)).style(style::card()))
         .push(vertical_space(Length::Fill))
         .push(
             Row::new()
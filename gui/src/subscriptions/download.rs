@@ -1,68 +1,48 @@
 // SPDX-FileCopyrightText: 2023 Manuel Quarneti <manuelquarneti@protonmail.com>
 // SPDX-License-Identifier: GPL-3.0-only
 
+use std::sync::mpsc::Receiver;
+
 use iced::{subscription, Subscription};
-use lib::DownloadQueue;
+use lib::tasks::{TaskEvent, TaskGraph};
+use lib::Context;
 
 enum State {
-    Ready(DownloadQueue),
-    Downloading {
-        queue: DownloadQueue,
-        total: usize,
-        downloaded: usize,
-    },
+    Ready(TaskGraph, Context),
+    Running(Receiver<TaskEvent>),
     Finished,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Progress {
     Started,
-    Advanced(f32),
-    Finished,
+    Task(TaskEvent),
     Errored,
 }
 
-pub fn files(queue: DownloadQueue) -> Subscription<Progress> {
+pub fn files(graph: TaskGraph, ctx: Context) -> Subscription<Progress> {
     struct DownloadFiles;
 
     subscription::unfold(
         std::any::TypeId::of::<DownloadFiles>(),
-        State::Ready(queue),
+        State::Ready(graph, ctx),
         download,
     )
 }
 
 async fn download(state: State) -> (Progress, State) {
     match state {
-        State::Ready(queue) => (
-            Progress::Advanced(0.0),
-            State::Downloading {
-                total: queue.len(),
-                queue,
-                downloaded: 0,
-            },
-        ),
-        State::Downloading {
-            mut queue,
-            total,
-            downloaded,
-        } => match queue.download_next() {
-            Ok(true) => {
-                let downloaded = downloaded + 1;
-                let percentage = (downloaded as f32 / total as f32) * 100.0;
+        State::Ready(graph, ctx) => (Progress::Started, State::Running(graph.start(ctx))),
+        State::Running(events) => {
+            // waiting for the next event blocks, so it can't happen on the executor
+            let (event, events) = lib::blocking::run(move || (events.recv(), events)).await;
 
-                (
-                    Progress::Advanced(percentage),
-                    State::Downloading {
-                        queue,
-                        total,
-                        downloaded,
-                    },
-                )
+            match event {
+                Ok(TaskEvent::Finished) => (Progress::Task(TaskEvent::Finished), State::Finished),
+                Ok(event) => (Progress::Task(event), State::Running(events)),
+                Err(_) => (Progress::Errored, State::Finished),
             }
-            Ok(false) => (Progress::Finished, State::Finished),
-            Err(_) => (Progress::Errored, State::Finished),
-        },
+        }
         State::Finished => iced::futures::future::pending().await,
     }
 }
@@ -4,62 +4,207 @@
 use crate::subscriptions::download;
 use crate::types::messages::Message;
 use iced::Subscription;
-use lib::DownloadQueue;
+use lib::tasks::{TaskEvent, TaskGraph, TaskState};
+use lib::vanilla_installer::RepairReport;
+use lib::{Context, DownloadProgress};
+
+pub struct Task {
+    pub name: String,
+    pub state: TaskState,
+    pub progress: Option<DownloadProgress>,
+}
+
+impl Task {
+    /// Completion in the range 0..=100.
+    pub fn percentage(&self) -> f32 {
+        match (&self.state, &self.progress) {
+            (TaskState::Done, _) => 100.0,
+            (_, Some(progress)) => progress.percentage(),
+            _ => 0.0,
+        }
+    }
+}
 
 pub enum State {
     Idle,
-    Downloading { progress: f32, queue: DownloadQueue },
+    Downloading {
+        graph: TaskGraph,
+        failed: Vec<String>,
+        paused: bool,
+    },
     Finished,
-    Errored,
+    Cancelled,
+    Errored {
+        graph: TaskGraph,
+        failed: Vec<String>,
+    },
+}
+
+fn set_state(tasks: &mut [Task], id: usize, state: TaskState) {
+    if let Some(task) = tasks.get_mut(id) {
+        task.state = state;
+    }
 }
 
 pub struct Download {
     pub state: State,
+    pub tasks: Vec<Task>,
+    pub repair_report: Option<RepairReport>,
 }
 
 impl Default for Download {
     fn default() -> Self {
-        Self { state: State::Idle }
+        Self {
+            state: State::Idle,
+            tasks: Vec::new(),
+            repair_report: None,
+        }
     }
 }
 
 impl Download {
-    pub fn start(&mut self, queue: DownloadQueue) {
-        match self.state {
-            State::Idle { .. } | State::Finished { .. } | State::Errored { .. } => {
-                self.state = State::Downloading {
-                    progress: 0.0,
-                    queue,
-                };
-            }
-            _ => {}
+    fn run(&mut self, graph: TaskGraph) {
+        self.tasks = graph
+            .states()
+            .into_iter()
+            .enumerate()
+            .map(|(id, state)| Task {
+                name: graph.name(id).to_string(),
+                state,
+                progress: None,
+            })
+            .collect();
+
+        self.state = State::Downloading {
+            graph,
+            failed: Vec::new(),
+            paused: false,
+        };
+    }
+
+    pub fn is_running(&self) -> bool {
+        matches!(self.state, State::Downloading { .. })
+    }
+
+    /// Starts `graph`, unless another download is running. Returns whether it started.
+    pub fn start(&mut self, graph: TaskGraph) -> bool {
+        if self.is_running() {
+            return false;
+        }
+
+        self.repair_report = None;
+        self.run(graph);
+        true
+    }
+
+    pub fn start_repair(&mut self, graph: TaskGraph, report: RepairReport) -> bool {
+        let started = self.start(graph);
+
+        if started {
+            self.repair_report = Some(report);
+        }
+
+        started
+    }
+
+    /// Runs the tasks that failed again.
+    pub fn retry(&mut self) {
+        if let State::Errored { graph, .. } = &self.state {
+            let graph = graph.retry();
+            self.run(graph);
+        }
+    }
+
+    pub fn pause(&mut self) {
+        if let State::Downloading { graph, paused, .. } = &mut self.state {
+            graph.control().pause();
+            *paused = true;
+        }
+    }
+
+    pub fn resume(&mut self) {
+        if let State::Downloading { graph, paused, .. } = &mut self.state {
+            graph.control().resume();
+            *paused = false;
+        }
+    }
+
+    /// Stops the download, the state changes once the workers have cleaned up.
+    pub fn cancel(&self) {
+        if let State::Downloading { graph, .. } = &self.state {
+            graph.control().cancel();
         }
     }
 
-    pub fn subscription(&self) -> Subscription<Message> {
+    /// Overall completion in the range 0..=100.
+    pub fn percentage(&self) -> f32 {
+        if self.tasks.is_empty() {
+            return 0.0;
+        }
+
+        self.tasks.iter().map(Task::percentage).sum::<f32>() / self.tasks.len() as f32
+    }
+
+    pub fn subscription(&self, ctx: &Context) -> Subscription<Message> {
         match &self.state {
-            State::Downloading { progress: _, queue } => {
-                download::files(queue.clone()).map(Message::DownloadProgressed)
+            State::Downloading { graph, .. } => {
+                download::files(graph.clone(), ctx.clone()).map(Message::DownloadProgressed)
             }
             _ => Subscription::none(),
         }
     }
 
     pub fn update(&mut self, new_progress: download::Progress) {
-        if let State::Downloading { progress, queue: _ } = &mut self.state {
-            match new_progress {
-                download::Progress::Started => {
-                    *progress = 0.0;
+        let State::Downloading { graph, failed, .. } = &mut self.state else {
+            return;
+        };
+
+        match new_progress {
+            download::Progress::Started => {}
+            download::Progress::Task(event) => match event {
+                TaskEvent::Started(id) => {
+                    set_state(&mut self.tasks, id, TaskState::Running);
+                }
+                TaskEvent::Progress(id, progress) => {
+                    if let Some(task) = self.tasks.get_mut(id) {
+                        task.progress = Some(progress);
+                    }
                 }
-                download::Progress::Advanced(percentage) => {
-                    *progress = percentage;
+                TaskEvent::ItemFailed { url, error, .. } => {
+                    failed.push(format!("{url}: {error}"));
                 }
-                download::Progress::Finished => {
-                    self.state = State::Finished;
+                TaskEvent::Done(id) => {
+                    set_state(&mut self.tasks, id, TaskState::Done);
                 }
-                download::Progress::Errored => {
-                    self.state = State::Errored;
+                TaskEvent::Failed(id, error) => {
+                    set_state(&mut self.tasks, id, TaskState::Failed(error));
                 }
+                TaskEvent::Skipped(id) => {
+                    set_state(&mut self.tasks, id, TaskState::Skipped);
+                }
+                TaskEvent::Cancelled(id) => {
+                    set_state(&mut self.tasks, id, TaskState::Cancelled);
+                }
+                TaskEvent::Finished => {
+                    let states = graph.states();
+
+                    if states.iter().all(|state| *state == TaskState::Done) {
+                        self.state = State::Finished;
+                    } else if states.contains(&TaskState::Cancelled) {
+                        self.state = State::Cancelled;
+                    } else {
+                        self.state = State::Errored {
+                            graph: graph.clone(),
+                            failed: std::mem::take(failed),
+                        };
+                    }
+                }
+            },
+            download::Progress::Errored => {
+                self.state = State::Errored {
+                    graph: graph.clone(),
+                    failed: std::mem::take(failed),
+                };
             }
         }
     }
@@ -0,0 +1,12 @@
+// SPDX-FileCopyrightText: 2023 Manuel Quarneti <manuelquarneti@protonmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use lib::runtimes::{RuntimeOverview, RuntimeUpdate};
+
+#[derive(Default)]
+pub struct Java {
+    /// Loaded every time the page is opened
+    pub overview: Option<RuntimeOverview>,
+    /// Found by the last check
+    pub updates: Option<Vec<RuntimeUpdate>>,
+}
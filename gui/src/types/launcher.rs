@@ -6,26 +6,39 @@ use iced::{clipboard, Command, Subscription};
 use rfd::{MessageButtons, MessageDialog, MessageDialogResult, MessageLevel};
 
 use crate::pages::Page;
-use crate::types::download::Download;
+use crate::types::download::{Download, State};
+use crate::types::java::Java;
 use crate::types::login::Login;
 use crate::types::messages::Message;
 use crate::types::modrinth_modpacks::ModrinthModpacks;
 use crate::types::vanilla_installer::VanillaInstaller;
 use lib::accounts::{Account, Accounts};
+use lib::gc::GarbageReport;
 use lib::instances::Instances;
+use lib::jobs::InstallJob;
 use lib::settings::Settings;
+use lib::tasks::TaskGraph;
+use lib::Context;
+
+const DOWNLOAD_RUNNING: &str = "Wait for the current download to finish first";
 
 pub struct Launcher {
     pub name: &'static str,
     pub page: Page,
     pub instances: Instances,
     pub settings: Settings,
+    pub ctx: Context,
     pub accounts: Accounts,
     pub login: Login,
     pub offline_account_username: String,
     pub vanilla_installer: VanillaInstaller,
     pub modrinth_modpacks: ModrinthModpacks,
     pub download: Download,
+    pub java: Java,
+    /// Unused files found by the last dry run
+    pub garbage: Option<GarbageReport>,
+    /// Bytes freed by the last collection
+    pub freed_space: Option<u64>,
 }
 
 fn error_dialog(error: &str) {
@@ -63,43 +76,115 @@ impl Default for Launcher {
             }
         };
 
+        let ctx = Context::new(&settings);
+
         Self {
             name: "CrabLauncher",
             page: Page::Instances,
             instances,
             settings,
+            ctx,
             accounts,
             login: Login::default(),
             offline_account_username: String::new(),
             vanilla_installer: VanillaInstaller::default(),
             modrinth_modpacks: ModrinthModpacks::default(),
             download: Download::default(),
+            java: Java::default(),
+            garbage: None,
+            freed_space: None,
         }
     }
 }
 
 impl Launcher {
     pub fn new() -> (Self, Command<Message>) {
-        let launcher = Self::default();
-        let mut commands = Vec::new();
-
-        // check for updates
-        if cfg!(feature = "updater") && launcher.settings.check_for_updates {
-            commands.push(Command::perform(
-                lib::updater::check_for_updates().map_err(|e| e.to_string()),
-                Message::GotUpdate,
-            ));
-        }
+        let mut launcher = Self::default();
+        launcher.resume_jobs();
+        let command = launcher.detect_offline();
+
+        (launcher, command)
+    }
+
+    /// Asks what to do with the installs that were interrupted last time.
+    fn resume_jobs(&mut self) {
+        let jobs = match InstallJob::load_all() {
+            Ok(jobs) => jobs,
+            Err(error) => {
+                error_dialog(&error.to_string());
+                return;
+            }
+        };
+
+        for job in jobs {
+            // one download at a time, the others are offered again on the next start
+            let resumable = job.can_resume() && matches!(self.download.state, State::Idle);
 
-        // fetch account head
-        if let Some(account) = &launcher.accounts.active {
-            commands.push(Command::perform(
-                lib::accounts::get_head(account.to_owned()).map_err(|e| e.to_string()),
-                Message::GotAccountHead,
-            ));
+            let mut buttons = vec!["Resume", "Roll back", "Later"];
+            if !resumable {
+                buttons.remove(0);
+            }
+
+            let result = MessageDialog::new()
+                .set_level(MessageLevel::Info)
+                .set_title("Unfinished install")
+                .set_description(format!(
+                    "{} was interrupted. Do you want to {}?",
+                    job.description,
+                    if resumable {
+                        "resume it or roll it back"
+                    } else if job.can_resume() {
+                        "roll it back now, or pick Later to resume it on the next start"
+                    } else {
+                        "roll it back"
+                    }
+                ))
+                .set_buttons(match buttons[..] {
+                    [first, second, third] => MessageButtons::YesNoCancelCustom(
+                        first.to_string(),
+                        second.to_string(),
+                        third.to_string(),
+                    ),
+                    _ => MessageButtons::OkCancelCustom(
+                        buttons[0].to_string(),
+                        buttons[1].to_string(),
+                    ),
+                })
+                .show();
+
+            // depending on the platform, custom buttons come back either as yes/no or by label
+            let button = match result {
+                MessageDialogResult::Yes | MessageDialogResult::Ok => buttons[0].to_string(),
+                MessageDialogResult::No => buttons[1].to_string(),
+                MessageDialogResult::Custom(button) => button,
+                _ => continue,
+            };
+
+            match button.as_str() {
+                "Resume" => match job.resume() {
+                    Ok(graph) => {
+                        self.download.start(self.configure_graph(graph));
+                        self.page = Page::Download;
+                    }
+                    Err(error) => error_dialog(&error.to_string()),
+                },
+                "Roll back" => {
+                    if let Err(error) = job.rollback(&mut self.instances) {
+                        error_dialog(&error.to_string());
+                    }
+                }
+                _ => {}
+            }
         }
+    }
 
-        (launcher, Command::batch(commands))
+    fn detect_offline(&self) -> Command<Message> {
+        let ctx = self.ctx.clone();
+
+        Command::perform(
+            lib::blocking::run(move || ctx.detect_offline()),
+            Message::DetectedOffline,
+        )
     }
 
     pub fn update(&mut self, message: Message) -> Command<Message> {
@@ -109,11 +194,18 @@ impl Launcher {
                     self.vanilla_installer = VanillaInstaller::default();
                     self.page = page;
                     return Command::perform(
-                        lib::vanilla_installer::get_versions().map_err(|e| e.to_string()),
+                        lib::vanilla_installer::get_versions(self.ctx.clone())
+                            .map_err(|e| e.to_string()),
                         Message::GotVersions,
                     );
                 }
 
+                if page == Page::Java {
+                    self.java = Java::default();
+                    self.page = page;
+                    return self.load_runtimes();
+                }
+
                 self.page = page;
             }
             Message::Error(error, fatal) => {
@@ -128,6 +220,32 @@ impl Launcher {
                     error_dialog(&error);
                 }
             }
+            Message::DetectedOffline(true) => {
+                println!("Running in offline mode");
+            }
+            Message::DetectedOffline(false) => {
+                let mut commands = Vec::new();
+
+                // check for updates
+                if cfg!(feature = "updater") && self.settings.check_for_updates {
+                    commands.push(Command::perform(
+                        lib::updater::check_for_updates(self.ctx.clone())
+                            .map_err(|e| e.to_string()),
+                        Message::GotUpdate,
+                    ));
+                }
+
+                // fetch account head
+                if let Some(account) = &self.accounts.active {
+                    commands.push(Command::perform(
+                        lib::accounts::get_head(self.ctx.clone(), account.to_owned())
+                            .map_err(|e| e.to_string()),
+                        Message::GotAccountHead,
+                    ));
+                }
+
+                return Command::batch(commands);
+            }
             Message::OpenURL(url) => {
                 if let Err(error) = open::that(url) {
                     return self.update(Message::Error(error.to_string(), false));
@@ -169,14 +287,46 @@ impl Launcher {
                 return self.update(Message::Error(error, true));
             }
             Message::LaunchInstance(name) => {
-                if let Some(account) = &self.accounts.active {
-                    if let Err(error) = self.instances.launch(&name, account) {
-                        return self.update(Message::Error(error.to_string(), true));
-                    }
+                if let Some(account) = self.accounts.active.clone() {
+                    let refresh = lib::accounts::refresh_account(self.ctx.clone(), account.clone());
+
+                    return Command::perform(
+                        async move {
+                            match refresh.await {
+                                Ok(account) => account,
+                                Err(error) => {
+                                    println!(
+                                        "Failed to refresh account, using cached credentials: {}",
+                                        error
+                                    );
+                                    account
+                                }
+                            }
+                        },
+                        move |account| Message::RefreshedAccount(name, account),
+                    );
                 } else {
                     return self.update(Message::Error("No account selected".to_string(), false));
                 }
             }
+            Message::RefreshedAccount(name, account) => {
+                if self.accounts.active.as_ref() != Some(&account) {
+                    if let Err(error) = self.accounts.update_account(&account) {
+                        return self.update(Message::Error(error.to_string(), false));
+                    }
+                }
+
+                return Command::perform(
+                    self.instances
+                        .launch(&self.ctx, &name, &account)
+                        .map_err(|e| e.to_string()),
+                    Message::InstanceExited,
+                );
+            }
+            Message::InstanceExited(Ok(())) => {}
+            Message::InstanceExited(Err(error)) => {
+                return self.update(Message::Error(error, true));
+            }
             Message::OpenInstanceFolder(name) => {
                 let path = self.instances.get_dir(&name);
 
@@ -191,6 +341,47 @@ impl Launcher {
                     return self.update(Message::Error(error.to_string(), false));
                 }
             }
+            Message::RepairInstance(name) => {
+                if self.download.is_running() {
+                    return self.update(Message::Error(DOWNLOAD_RUNNING.to_string(), false));
+                }
+
+                return Command::perform(
+                    self.instances
+                        .repair(&self.ctx, &name)
+                        .map_err(|e| e.to_string()),
+                    move |result| Message::CheckedInstance(name, result),
+                );
+            }
+            Message::CheckedInstance(name, result) => {
+                // something else may have started while the files were being checked
+                if self.download.is_running() {
+                    return self.update(Message::Error(DOWNLOAD_RUNNING.to_string(), false));
+                }
+
+                let result = result.and_then(|(queue, report)| {
+                    let queue = queue
+                        .with_job(&format!("Repairing {}", name), None)
+                        .map_err(|e| e.to_string())?;
+                    Ok((TaskGraph::from_queue("Repair files", queue), report))
+                });
+
+                match result {
+                    Ok((graph, report)) => {
+                        if !self
+                            .download
+                            .start_repair(self.configure_graph(graph), report)
+                        {
+                            return self
+                                .update(Message::Error(DOWNLOAD_RUNNING.to_string(), false));
+                        }
+                        self.page = Page::Download;
+                    }
+                    Err(error) => {
+                        return self.update(Message::Error(error, false));
+                    }
+                }
+            }
             Message::DeleteInstance(name) => {
                 let result = MessageDialog::new()
                     .set_title("Delete instance")
@@ -206,7 +397,8 @@ impl Launcher {
             }
             Message::GetVersions => {
                 return Command::perform(
-                    lib::vanilla_installer::get_versions().map_err(|e| e.to_string()),
+                    lib::vanilla_installer::get_versions(self.ctx.clone())
+                        .map_err(|e| e.to_string()),
                     Message::GotVersions,
                 );
             }
@@ -235,30 +427,57 @@ impl Launcher {
                 let optimize_jvm = self.vanilla_installer.optimize_jvm;
                 let memory = self.vanilla_installer.memory.clone();
 
-                if let Err(error) = self
-                    .instances
-                    .create(name, version, None, optimize_jvm, memory)
+                if self.download.is_running() {
+                    return self.update(Message::Error(DOWNLOAD_RUNNING.to_string(), false));
+                }
+
+                if let Err(error) =
+                    self.instances
+                        .create(name.clone(), version.clone(), None, optimize_jvm, memory)
                 {
                     return self.update(Message::Error(error.to_string(), true));
-                } else {
-                    self.page = Page::Instances;
-                    self.vanilla_installer = VanillaInstaller::default();
-                    //return self.update(Message::UpdateInstances);
                 }
+
+                // installs whatever the instance runs on, Java overrides included
+                let instance = &self.instances.list[&name];
+                let graph = lib::vanilla_installer::download_version(
+                    &instance.minecraft,
+                    instance.fabric.as_deref(),
+                    instance.java,
+                    instance.java_release.as_deref(),
+                )
+                .with_job(&format!("Installing {}", name), Some(&name));
+
+                self.download.start(self.configure_graph(graph));
+                self.page = Page::Download;
+                self.vanilla_installer = VanillaInstaller::default();
             }
             Message::AddAccount => {
                 let client = Accounts::get_client().unwrap();
-                let details = Accounts::get_details(&client).unwrap();
+                self.page = Page::AddingAccount;
+
+                return Command::perform(
+                    Accounts::get_details(self.ctx.clone(), client).map_err(|e| e.to_string()),
+                    Message::GotLoginDetails,
+                );
+            }
+            Message::GotLoginDetails(Ok(details)) => {
+                let client = Accounts::get_client().unwrap();
 
                 self.login.url = details.verification_uri().to_string();
                 self.login.code = details.user_code().secret().to_string();
-                self.page = Page::AddingAccount;
 
                 return Command::perform(
-                    Accounts::get_account(client, details).map_err(|e| e.to_string()),
+                    Accounts::get_account(self.ctx.clone(), client, details)
+                        .map_err(|e| e.to_string()),
                     Message::LoggedIn,
                 );
             }
+            Message::GotLoginDetails(Err(error)) => {
+                self.page = Page::Accounts;
+
+                return self.update(Message::Error(error, false));
+            }
             Message::LoggedIn(Ok(account)) => {
                 self.login = Login::default();
 
@@ -317,14 +536,125 @@ impl Launcher {
             Message::SetCheckForUpdates(check_for_updates) => {
                 self.settings.check_for_updates = check_for_updates;
             }
+            Message::SetOffline(offline) => {
+                self.settings.offline = offline;
+            }
+            Message::SetMaxDownloadSpeed(speed) => {
+                if let Some(speed) = parse_speed(&speed) {
+                    self.settings.max_download_speed = speed;
+                }
+            }
+            Message::SetMaxBackgroundDownloadSpeed(speed) => {
+                if let Some(speed) = parse_speed(&speed) {
+                    self.settings.max_background_download_speed = speed;
+                }
+            }
             Message::SaveSettings => {
                 if let Err(error) = self.settings.save() {
                     return self.update(Message::Error(error.to_string(), false));
                 }
+
+                self.ctx.update_settings(&self.settings);
+            }
+            Message::FindGarbage => {
+                self.freed_space = None;
+
+                return Command::perform(
+                    self.instances
+                        .collect_garbage(true)
+                        .map_err(|e| e.to_string()),
+                    Message::FoundGarbage,
+                );
+            }
+            Message::FoundGarbage(Ok(report)) => {
+                self.garbage = Some(report);
+            }
+            Message::CollectGarbage => {
+                self.garbage = None;
+
+                return Command::perform(
+                    self.instances
+                        .collect_garbage(false)
+                        .map_err(|e| e.to_string()),
+                    Message::CollectedGarbage,
+                );
+            }
+            Message::CollectedGarbage(Ok(report)) => {
+                self.freed_space = Some(report.size);
+            }
+            Message::FoundGarbage(Err(error)) | Message::CollectedGarbage(Err(error)) => {
+                return self.update(Message::Error(error, false));
+            }
+            Message::GotRuntimes(Ok(overview)) => {
+                self.java.overview = Some(overview);
+            }
+            Message::CheckRuntimeUpdates => {
+                let ctx = self.ctx.clone();
+
+                return Command::perform(
+                    lib::blocking::run(move || lib::runtimes::check_updates(&ctx))
+                        .map_err(|e| e.to_string()),
+                    Message::GotRuntimeUpdates,
+                );
+            }
+            Message::GotRuntimeUpdates(Ok(updates)) => {
+                self.java.updates = Some(updates);
+            }
+            Message::UpdateRuntime(major) => {
+                if self.download.is_running() {
+                    return self.update(Message::Error(DOWNLOAD_RUNNING.to_string(), false));
+                }
+
+                let graph = lib::runtimes::download_update(major)
+                    .with_job(&format!("Updating Java {}", major), None);
+
+                self.download.start(self.configure_graph(graph));
+                self.page = Page::Download;
+            }
+            Message::DeleteRuntime(runtime) => {
+                let result = MessageDialog::new()
+                    .set_title("Delete Java runtime")
+                    .set_description(format!(
+                        "Are you sure you want to delete {}?",
+                        runtime.release
+                    ))
+                    .set_buttons(MessageButtons::YesNo)
+                    .show();
+
+                if result == MessageDialogResult::Yes {
+                    return Command::perform(
+                        lib::blocking::run(move || runtime.delete()).map_err(|e| e.to_string()),
+                        Message::RemovedRuntimes,
+                    );
+                }
+            }
+            Message::RemoveUnusedRuntimes => {
+                return Command::perform(
+                    self.instances
+                        .remove_unused_runtimes()
+                        .map_ok(|_| ())
+                        .map_err(|e| e.to_string()),
+                    Message::RemovedRuntimes,
+                );
+            }
+            Message::RemovedRuntimes(Ok(())) => {
+                return self.load_runtimes();
+            }
+            Message::PinRuntime(name, release) => {
+                if let Err(error) = self.instances.pin_runtime(&name, release) {
+                    return self.update(Message::Error(error.to_string(), false));
+                }
+
+                return self.load_runtimes();
+            }
+            Message::GotRuntimes(Err(error))
+            | Message::GotRuntimeUpdates(Err(error))
+            | Message::RemovedRuntimes(Err(error)) => {
+                return self.update(Message::Error(error, false));
             }
             Message::GetModpacks => {
                 return Command::perform(
-                    lib::modrinth::search_modpacks("").map_err(|e| e.to_string()),
+                    lib::modrinth::search_modpacks(self.ctx.clone(), "").map_err(|e| e.to_string()),
                     Message::GotModpacks,
                 );
             }
@@ -337,12 +667,49 @@ impl Launcher {
             Message::DownloadProgressed(progress) => {
                 self.download.update(progress);
             }
+            Message::PauseDownload => {
+                self.download.pause();
+            }
+            Message::ResumeDownload => {
+                self.download.resume();
+            }
+            Message::CancelDownload => {
+                self.download.cancel();
+            }
+            Message::RetryDownload => {
+                self.download.retry();
+            }
         }
 
         Command::none()
     }
 
+    fn load_runtimes(&self) -> Command<Message> {
+        Command::perform(
+            self.instances.get_runtimes().map_err(|e| e.to_string()),
+            Message::GotRuntimes,
+        )
+    }
+
+    fn configure_graph(&self, graph: TaskGraph) -> TaskGraph {
+        graph
+            .with_limits(
+                self.settings.max_concurrent_downloads,
+                self.settings.max_downloads_per_host,
+            )
+            .with_max_attempts(self.settings.max_download_attempts)
+    }
+
     pub fn subscription(&self) -> Subscription<Message> {
-        self.download.subscription()
+        self.download.subscription(&self.ctx)
+    }
+}
+
+/// An empty field means no limit, anything that isn't a number is ignored.
+fn parse_speed(speed: &str) -> Option<Option<u64>> {
+    if speed.is_empty() {
+        Some(None)
+    } else {
+        speed.parse().ok().map(Some)
     }
 }
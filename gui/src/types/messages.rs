@@ -3,23 +3,36 @@
 
 use crate::pages::Page;
 use crate::subscriptions::download;
-use lib::accounts::Account;
+use lib::accounts::{Account, LoginDetails};
+use lib::gc::GarbageReport;
 use lib::instances::Instance;
 use lib::modrinth::Projects;
+use lib::runtimes::{Runtime, RuntimeOverview, RuntimeUpdate};
+use lib::vanilla_installer::RepairReport;
+use lib::DownloadQueue;
 
 #[derive(Debug, Clone)]
 pub enum Message {
     ChangePage(Page),
     Error(String, bool),
+    DetectedOffline(bool),
     OpenURL(String),
     GotUpdate(Result<Option<(String, String)>, String>),
     GotAccountHead(Result<Account, String>),
     CreatedInstance(Result<(), String>),
     LaunchInstance(String),
+    RefreshedAccount(String, Account),
+    InstanceExited(Result<(), String>),
     OpenInstanceFolder(String),
     OpenInstanceConfig(String),
+    RepairInstance(String),
+    CheckedInstance(String, Result<(DownloadQueue, RepairReport), String>),
     DeleteInstance(String),
     DownloadProgressed(download::Progress),
+    PauseDownload,
+    ResumeDownload,
+    CancelDownload,
+    RetryDownload,
 
     // Vanilla installer
     GetVersions,
@@ -32,6 +45,7 @@ pub enum Message {
 
     // Accounts
     AddAccount,
+    GotLoginDetails(Result<LoginDetails, String>),
     LoggedIn(Result<Account, String>),
     SelectAccount(Account),
     RemoveAccount(Account),
@@ -41,7 +55,24 @@ pub enum Message {
 
     // Settings
     SetCheckForUpdates(bool),
+    SetOffline(bool),
+    SetMaxDownloadSpeed(String),
+    SetMaxBackgroundDownloadSpeed(String),
     SaveSettings,
+    FindGarbage,
+    FoundGarbage(Result<GarbageReport, String>),
+    CollectGarbage,
+    CollectedGarbage(Result<GarbageReport, String>),
+
+    // Java
+    GotRuntimes(Result<RuntimeOverview, String>),
+    CheckRuntimeUpdates,
+    GotRuntimeUpdates(Result<Vec<RuntimeUpdate>, String>),
+    UpdateRuntime(u32),
+    DeleteRuntime(Runtime),
+    RemoveUnusedRuntimes,
+    RemovedRuntimes(Result<(), String>),
+    PinRuntime(String, Option<String>),
 
     // Modrinth
     GetModpacks,
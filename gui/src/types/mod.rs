@@ -7,3 +7,4 @@ pub mod vanilla_installer;
 pub mod login;
 pub mod modrinth_modpacks;
 pub mod download;
+pub mod java;
@@ -1,10 +1,10 @@
 // SPDX-FileCopyrightText: 2023 Manuel Quarneti <manuelquarneti@protonmail.com>
 // SPDX-License-Identifier: GPL-3.0-only
 
-use std::{fs, io, thread};
+use std::{fs, thread};
 
 use anyhow::Result;
-use oauth2::ureq::http_client;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
 use oauth2::{
     basic::BasicClient, devicecode::StandardDeviceAuthorizationResponse, url, AuthUrl, ClientId,
     DeviceAuthorizationUrl, ExtraTokenFields, RefreshToken, Scope, StandardTokenResponse,
@@ -16,7 +16,8 @@ use serde_with::{base64::Base64, serde_as};
 use time::{Duration, OffsetDateTime};
 
 use crate::paths::ACCOUNTS_PATH;
-use crate::AGENT;
+use crate::transport::Request;
+use crate::{blocking, Context};
 
 pub const MSA_DEVICE_AUTH_ENDPOINT: &str =
     "https://login.microsoftonline.com/consumers/oauth2/v2.0/devicecode";
@@ -32,6 +33,9 @@ const MINECRAFT_PROFILE_ENDPOINT: &str = "https://api.minecraftservices.com/mine
 pub const CLIENT_ID: &str = "543a897a-0694-435b-a147-11de17aacd1f";
 pub const SCOPES: &[&str] = &["XboxLive.signin"];
 
+/// The code and URL the user logs in with
+pub type LoginDetails = StandardDeviceAuthorizationResponse;
+
 #[serde_as]
 #[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
 pub struct Account {
@@ -67,9 +71,36 @@ impl Account {
             cached_head_time: None,
         }
     }
+
+    /// The Xbox user ID, read from the Minecraft token. Offline accounts don't have one.
+    pub fn get_xuid(&self) -> Option<String> {
+        #[derive(Deserialize)]
+        struct Claims {
+            xuid: String,
+        }
+
+        let payload = self.mc_access_token.split('.').nth(1)?;
+        let payload = URL_SAFE_NO_PAD.decode(payload.trim_end_matches('=')).ok()?;
+
+        serde_json::from_slice::<Claims>(&payload)
+            .ok()
+            .map(|claims| claims.xuid)
+    }
+
+    pub fn get_user_type(&self) -> &'static str {
+        if self.ms_refresh_token.is_some() {
+            "msa"
+        } else {
+            "legacy"
+        }
+    }
 }
 
-pub async fn get_head(mut account: Account) -> Result<Account> {
+pub async fn get_head(ctx: Context, mut account: Account) -> Result<Account> {
+    if ctx.is_offline() {
+        return Ok(account);
+    }
+
     let now = OffsetDateTime::now_utc();
 
     if let Some(time) = &account.cached_head_time {
@@ -78,12 +109,9 @@ pub async fn get_head(mut account: Account) -> Result<Account> {
         }
     }
 
-    let resp = AGENT
-        .get(&format!("https://crafatar.com/avatars/{}", account.mc_id))
-        .call()?;
-
-    let mut bytes = Vec::with_capacity(resp.header("Content-Length").unwrap().parse::<usize>()?);
-    io::copy(&mut resp.into_reader(), &mut bytes).unwrap();
+    let url = format!("https://crafatar.com/avatars/{}", account.mc_id);
+    let bytes =
+        blocking::run(move || -> Result<Vec<u8>> { Ok(ctx.get(&url)?.into_bytes()?) }).await?;
 
     account.cached_head = Some(bytes);
     account.cached_head_time = Some(now);
@@ -91,6 +119,39 @@ pub async fn get_head(mut account: Account) -> Result<Account> {
     Ok(account)
 }
 
+/// Gets a new Minecraft token if the current one is about to expire.
+///
+/// The refreshed account isn't saved, pass it to [`Accounts::update_account`] for that.
+pub async fn refresh_account(ctx: Context, account: Account) -> Result<Account> {
+    // launch with the cached credentials
+    if ctx.is_offline() {
+        return Ok(account);
+    }
+
+    let now = OffsetDateTime::now_utc();
+
+    if let Some(token_time) = account.token_time {
+        if now < token_time + Duration::minutes(30) {
+            return Ok(account);
+        }
+    }
+
+    let Some(refresh_token) = account.ms_refresh_token.clone() else {
+        return Ok(account);
+    };
+
+    blocking::run(move || {
+        let refresh_token = RefreshToken::new(refresh_token);
+
+        let token = Accounts::get_client()?
+            .exchange_refresh_token(&refresh_token)
+            .request(|request| ctx.oauth_request(request))?;
+
+        get_minecraft_account_data(&ctx, &token, now)
+    })
+    .await
+}
+
 #[derive(Serialize, Deserialize, Clone, Default)]
 pub struct Accounts {
     pub active: Option<Account>,
@@ -172,35 +233,42 @@ impl Accounts {
         Ok(client)
     }
 
-    pub fn get_details(client: &BasicClient) -> Result<StandardDeviceAuthorizationResponse> {
+    pub async fn get_details(ctx: Context, client: BasicClient) -> Result<LoginDetails> {
         let scopes = SCOPES
             .iter()
             .map(|s| Scope::new(s.to_string()))
             .collect::<Vec<_>>();
 
-        let details = client
-            .exchange_device_code()?
-            .add_scopes(scopes)
-            .request(http_client)?;
+        blocking::run(move || {
+            let details = client
+                .exchange_device_code()?
+                .add_scopes(scopes)
+                .request(|request| ctx.oauth_request(request))?;
 
-        Ok(details)
+            Ok(details)
+        })
+        .await
     }
 
+    /// Waits for the user to log in with the device code in `details`.
     pub async fn get_account(
+        ctx: Context,
         client: BasicClient,
-        details: StandardDeviceAuthorizationResponse,
+        details: LoginDetails,
     ) -> Result<Account> {
-        let token = client.exchange_device_access_token(&details).request(
-            http_client,
-            thread::sleep,
-            None,
-        )?;
-
-        let now = OffsetDateTime::now_utc();
-
-        let account = get_minecraft_account_data(&token, now)?;
-
-        Ok(account)
+        blocking::run(move || {
+            // polls until the user is done, so this can take minutes
+            let token = client.exchange_device_access_token(&details).request(
+                |request| ctx.oauth_request(request),
+                thread::sleep,
+                None,
+            )?;
+
+            let now = OffsetDateTime::now_utc();
+
+            get_minecraft_account_data(&ctx, &token, now)
+        })
+        .await
     }
 
     pub fn update_account(&mut self, account: &Account) -> Result<()> {
@@ -222,38 +290,13 @@ impl Accounts {
 
         Ok(())
     }
-
-    pub fn refresh_account(&mut self, account: Account) -> Result<Account> {
-        let now = OffsetDateTime::now_utc();
-
-        if let Some(token_time) = account.token_time {
-            if now < token_time + Duration::minutes(30) {
-                return Ok(account);
-            }
-        }
-
-        if let Some(refresh_token) = account.ms_refresh_token {
-            let refresh_token = RefreshToken::new(refresh_token);
-
-            let token = Self::get_client()?
-                .exchange_refresh_token(&refresh_token)
-                .request(http_client)?;
-
-            let account = get_minecraft_account_data(&token, now)?;
-
-            self.update_account(&account)?;
-
-            Ok(account)
-        } else {
-            Ok(account)
-        }
-    }
 }
 
 pub fn get_minecraft_account_data<A: ExtraTokenFields, B: TokenType>(
+    ctx: &Context,
     token: &StandardTokenResponse<A, B>,
     now: OffsetDateTime,
-) -> Result<Account, ureq::Error> {
+) -> Result<Account> {
     // Authenticate with Xbox Live
 
     #[derive(Deserialize)]
@@ -285,11 +328,7 @@ pub fn get_minecraft_account_data<A: ExtraTokenFields, B: TokenType>(
     });
 
     println!("Authenticating with Xbox Live...");
-    let xbl_response = AGENT
-        .post(XBOXLIVE_AUTH_ENDPOINT)
-        .set("Accept", "application/json")
-        .send_json(params)?
-        .into_json::<XBLResponse>()?;
+    let xbl_response = ctx.post_json::<XBLResponse>(XBOXLIVE_AUTH_ENDPOINT, &params)?;
     println!("Authenticated with Xbox Live!");
 
     // Authenticate with XSTS
@@ -310,11 +349,7 @@ pub fn get_minecraft_account_data<A: ExtraTokenFields, B: TokenType>(
     });
 
     println!("Authenticating with XSTS...");
-    let xsts_response = AGENT
-        .post(XSTS_AUTHORIZATION_ENDPOINT)
-        .set("Accept", "application/json")
-        .send_json(params)?
-        .into_json::<XSTSResponse>()?;
+    let xsts_response = ctx.post_json::<XSTSResponse>(XSTS_AUTHORIZATION_ENDPOINT, &params)?;
     println!("Authenticated with XSTS!");
 
     // Authenticate with Minecraft
@@ -333,11 +368,8 @@ pub fn get_minecraft_account_data<A: ExtraTokenFields, B: TokenType>(
     });
 
     println!("Authenticating with Minecraft...");
-    let minecraft_response = AGENT
-        .post(MINECRAFT_AUTH_ENDPOINT)
-        .set("Accept", "application/json")
-        .send_json(params)?
-        .into_json::<MinecraftResponse>()?;
+    let minecraft_response =
+        ctx.post_json::<MinecraftResponse>(MINECRAFT_AUTH_ENDPOINT, &params)?;
     println!("Authenticated with Minecraft!");
 
     // Get Minecraft profile
@@ -348,13 +380,11 @@ pub fn get_minecraft_account_data<A: ExtraTokenFields, B: TokenType>(
         name: String,
     }
 
-    let minecraft_profile = AGENT
-        .get(MINECRAFT_PROFILE_ENDPOINT)
-        .set(
+    let minecraft_profile = ctx
+        .send(Request::get(MINECRAFT_PROFILE_ENDPOINT).header(
             "Authorization",
             &format!("Bearer {}", minecraft_response.access_token),
-        )
-        .call()?
+        ))?
         .into_json::<MinecraftProfile>()?;
 
     let account = Account {
@@ -369,3 +399,68 @@ pub fn get_minecraft_account_data<A: ExtraTokenFields, B: TokenType>(
 
     Ok(account)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use oauth2::basic::BasicTokenType;
+    use oauth2::{AccessToken, EmptyExtraTokenFields};
+
+    use super::*;
+    use crate::endpoints::Endpoints;
+    use crate::transport::{MemoryTransport, Method};
+
+    #[test]
+    fn microsoft_token_is_exchanged_for_a_minecraft_account() {
+        let transport = Arc::new(MemoryTransport::new());
+        transport.add_json(
+            XBOXLIVE_AUTH_ENDPOINT,
+            &json!({ "Token": "xbl-token", "DisplayClaims": { "xui": [{ "uhs": "user-hash" }] } }),
+        );
+        transport.add_json(
+            XSTS_AUTHORIZATION_ENDPOINT,
+            &json!({ "Token": "xsts-token" }),
+        );
+        transport.add_json(
+            MINECRAFT_AUTH_ENDPOINT,
+            &json!({ "access_token": "mc-token" }),
+        );
+        transport.add_json(
+            MINECRAFT_PROFILE_ENDPOINT,
+            &json!({ "id": "0123456789abcdef", "name": "Steve" }),
+        );
+        let ctx = Context::with_transport(transport.clone(), Endpoints::default());
+
+        let mut token = StandardTokenResponse::new(
+            AccessToken::new("ms-token".to_string()),
+            BasicTokenType::Bearer,
+            EmptyExtraTokenFields {},
+        );
+        token.set_refresh_token(Some(RefreshToken::new("ms-refresh".to_string())));
+
+        let now = OffsetDateTime::now_utc();
+        let account = get_minecraft_account_data(&ctx, &token, now).unwrap();
+
+        assert_eq!(account.mc_id, "0123456789abcdef");
+        assert_eq!(account.mc_username, "Steve");
+        assert_eq!(account.mc_access_token, "mc-token");
+        assert_eq!(account.ms_refresh_token.as_deref(), Some("ms-refresh"));
+        assert_eq!(account.token_time, Some(now));
+
+        let requests = transport.requests();
+        let methods = requests.iter().map(|r| r.method).collect::<Vec<_>>();
+        assert_eq!(
+            methods,
+            [Method::Post, Method::Post, Method::Post, Method::Get]
+        );
+
+        let minecraft =
+            serde_json::from_slice::<serde_json::Value>(requests[2].body.as_deref().unwrap())
+                .unwrap();
+        assert_eq!(minecraft["identityToken"], "XBL3.0 x=user-hash;xsts-token");
+        assert!(requests[3]
+            .headers
+            .contains(&("Authorization".to_string(), "Bearer mc-token".to_string())));
+    }
+}
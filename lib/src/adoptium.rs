@@ -1,13 +1,11 @@
 // SPDX-FileCopyrightText: 2023 Manuel Quarneti <manuelquarneti@protonmail.com>
 // SPDX-License-Identifier: GPL-3.0-only
 
-use std::{fs, path::PathBuf};
-
-use anyhow::{anyhow, bail, Result};
+use anyhow::{anyhow, Result};
 use serde::Deserialize;
 
 use crate::paths::RUNTIMES_DIR;
-use crate::{DownloadItem, Hash, HashAlgorithm, AGENT};
+use crate::{cache, Context, DownloadItem, ExtractOptions, Hash, HashAlgorithm};
 
 #[cfg(target_os = "windows")]
 const OS: &str = "windows";
@@ -28,6 +26,7 @@ const ARCH: &str = "aarch64";
 struct Package {
     checksum: String,
     link: String,
+    size: u64,
 }
 
 #[derive(Deserialize)]
@@ -41,65 +40,147 @@ struct Assets {
     release_name: String,
 }
 
-pub fn install(java_version: &str) -> Result<Vec<DownloadItem>> {
-    let url = format!(
-        "https://api.adoptium.net/v3/assets/latest/{}/hotspot?architecture={}&image_type=jre&os={}&vendor=eclipse",
-        java_version, ARCH, OS
-    );
-
-    let assets = &AGENT.get(&url).call()?.into_json::<Vec<Assets>>()?[0];
-
-    let mut download_items = Vec::new();
+#[derive(Deserialize)]
+struct ReleaseAssets {
+    binaries: Vec<Binary>,
+    release_name: String,
+}
 
-    let path = RUNTIMES_DIR
-        .join(java_version)
-        .join(format!("{}-jre", assets.release_name));
+/// A Temurin JRE build for this platform.
+pub struct Release {
+    /// Like `jdk-17.0.9+9`
+    pub name: String,
+    package: Package,
+}
 
-    if !path.exists() {
-        let _ = fs::remove_dir_all(RUNTIMES_DIR.join(java_version));
+impl Release {
+    /// The archive of this release, unpacked into `RUNTIMES_DIR/<major>/<name>-jre`.
+    pub fn get_download_item(&self, java_major: u32) -> DownloadItem {
+        let path = RUNTIMES_DIR
+            .join(java_major.to_string())
+            .join(format!("{}-jre", self.name));
 
-        let url = assets.binary.package.link.to_owned();
-        let hash = Some(Hash {
-            hash: assets.binary.package.checksum.to_owned(),
-            function: HashAlgorithm::Sha256,
-        });
-        download_items.push(DownloadItem {
-            url,
+        DownloadItem {
+            url: self.package.link.to_owned(),
+            mirrors: vec![],
             path,
-            hash,
-            extract: true,
-        });
-    } else {
-        println!("Runtime already up to date");
+            hash: Some(Hash {
+                hash: self.package.checksum.to_owned(),
+                function: HashAlgorithm::Sha256,
+            }),
+            size: Some(self.package.size),
+            extract: Some(ExtractOptions {
+                strip_top_level: true,
+            }),
+        }
     }
+}
 
-    Ok(download_items)
+/// The newest release of `java_major`.
+pub fn get_latest(ctx: &Context, java_major: u32) -> Result<Release> {
+    let url = format!(
+        "{}/v3/assets/latest/{}/hotspot?architecture={}&image_type=jre&os={}&vendor=eclipse",
+        ctx.endpoints().adoptium_api,
+        java_major,
+        ARCH,
+        OS
+    );
+
+    let assets = ctx
+        .get_json_cached::<Vec<Assets>>(&url, cache::SHORT_TTL)?
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow!("No Java {} release found for this platform", java_major))?;
+
+    Ok(Release {
+        name: assets.release_name,
+        package: assets.binary.package,
+    })
 }
 
-pub fn get_path(java_version: &str) -> Result<PathBuf> {
-    let dir = RUNTIMES_DIR.join(java_version);
-    let runtime_dir = fs::read_dir(&dir)?
-        .filter_map(|entry| entry.ok())
-        .filter(|entry| entry.file_type().unwrap().is_dir())
+/// A release by name, to install the one an instance is pinned to.
+pub fn get_release(ctx: &Context, release_name: &str) -> Result<Release> {
+    let url = format!(
+        "{}/v3/assets/release_name/eclipse/{}?architecture={}&heap_size=normal&image_type=jre&jvm_impl=hotspot&os={}&project=jdk",
+        ctx.endpoints().adoptium_api,
+        release_name.replace('+', "%2B"),
+        ARCH,
+        OS
+    );
+
+    let assets = ctx.get_json_cached::<ReleaseAssets>(&url, cache::LONG_TTL)?;
+    let binary = assets
+        .binaries
+        .into_iter()
         .next()
-        .ok_or_else(|| anyhow!("No runtime found for version {}", java_version))?;
-    let runtime_dir = runtime_dir.path();
-
-    let runtime_path = if cfg!(target_os = "windows") {
-        runtime_dir.join("bin").join("java.exe")
-    } else if cfg!(target_os = "macos") {
-        runtime_dir
-            .join("Contents")
-            .join("Home")
-            .join("bin")
-            .join("java")
-    } else {
-        runtime_dir.join("bin").join("java")
-    };
-
-    if !runtime_path.exists() {
-        bail!("No runtime found for version {}", java_version);
+        .ok_or_else(|| anyhow!("{} is not available for this platform", release_name))?;
+
+    Ok(Release {
+        name: assets.release_name,
+        package: binary.package,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::cache::MetaCache;
+    use crate::endpoints::Endpoints;
+    use crate::transport::MemoryTransport;
+
+    #[test]
+    fn latest_release_comes_from_the_api() {
+        let dir = tempfile::tempdir().unwrap();
+        let transport = Arc::new(MemoryTransport::new());
+        let ctx = Context::with_transport(transport.clone(), Endpoints::default())
+            .with_cache(MetaCache::new(dir.path()));
+
+        let url = format!(
+            "https://api.adoptium.net/v3/assets/latest/17/hotspot?architecture={}&image_type=jre&os={}&vendor=eclipse",
+            ARCH, OS
+        );
+        transport.add_json(
+            &url,
+            &json!([{
+                "release_name": "jdk-17.0.9+9",
+                "binary": {
+                    "package": {
+                        "checksum": "abc",
+                        "link": "https://github.com/adoptium/jre.tar.gz",
+                        "size": 42,
+                    },
+                },
+            }]),
+        );
+
+        let release = get_latest(&ctx, 17).unwrap();
+
+        assert_eq!(release.name, "jdk-17.0.9+9");
+        assert_eq!(
+            release.package.link,
+            "https://github.com/adoptium/jre.tar.gz"
+        );
+        assert_eq!(release.package.size, 42);
+        assert_eq!(transport.requests()[0].url, url);
     }
 
-    Ok(runtime_path)
+    #[test]
+    fn no_release_for_this_platform_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let transport = Arc::new(MemoryTransport::new());
+        let ctx = Context::with_transport(transport.clone(), Endpoints::default())
+            .with_cache(MetaCache::new(dir.path()));
+
+        let url = format!(
+            "https://api.adoptium.net/v3/assets/latest/8/hotspot?architecture={}&image_type=jre&os={}&vendor=eclipse",
+            ARCH, OS
+        );
+        transport.add_json(&url, &json!([]));
+
+        assert!(get_latest(&ctx, 8).is_err());
+    }
 }
@@ -0,0 +1,113 @@
+// SPDX-FileCopyrightText: 2023 Manuel Quarneti <manuelquarneti@protonmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    collections::VecDeque,
+    sync::{Condvar, Mutex},
+    thread,
+    time::Duration,
+};
+
+use futures_channel::oneshot;
+use once_cell::sync::Lazy;
+
+/// Threads with nothing to do exit after this long
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+type Job = Box<dyn FnOnce() + Send>;
+
+#[derive(Default)]
+struct State {
+    queue: VecDeque<Job>,
+    idle: usize,
+}
+
+#[derive(Default)]
+struct Pool {
+    state: Mutex<State>,
+    job_added: Condvar,
+}
+
+static POOL: Lazy<Pool> = Lazy::new(Pool::default);
+
+fn worker() {
+    let mut state = POOL.state.lock().unwrap();
+
+    loop {
+        if let Some(job) = state.queue.pop_front() {
+            drop(state);
+            job();
+            state = POOL.state.lock().unwrap();
+            continue;
+        }
+
+        state.idle += 1;
+        let (guard, result) = POOL
+            .job_added
+            .wait_timeout_while(state, IDLE_TIMEOUT, |state| state.queue.is_empty())
+            .unwrap();
+        state = guard;
+        state.idle -= 1;
+
+        if result.timed_out() {
+            return;
+        }
+    }
+}
+
+fn submit(job: Job) {
+    let mut state = POOL.state.lock().unwrap();
+    state.queue.push_back(job);
+
+    // there are never fewer threads than jobs, so a long call can't hold up the others
+    if state.queue.len() > state.idle {
+        thread::Builder::new()
+            .name("blocking".to_string())
+            .spawn(worker)
+            .expect("failed to spawn a blocking thread");
+    } else {
+        POOL.job_added.notify_one();
+    }
+}
+
+/// Runs blocking work on a pool of threads, so awaiting it never stalls the executor.
+pub async fn run<T, F>(f: F) -> T
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let (tx, rx) = oneshot::channel();
+
+    submit(Box::new(move || {
+        let _ = tx.send(f());
+    }));
+
+    rx.await.expect("blocking task panicked")
+}
+
+/// Drives a future to completion on the current thread, for tests of the async API.
+#[cfg(test)]
+pub(crate) fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    use std::sync::Arc;
+    use std::task::{Context, Poll, Wake, Waker};
+
+    struct Unparker(thread::Thread);
+
+    impl Wake for Unparker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    let waker = Waker::from(Arc::new(Unparker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    let mut future = std::pin::pin!(future);
+
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+
+        thread::park();
+    }
+}
@@ -0,0 +1,274 @@
+// SPDX-FileCopyrightText: 2023 Manuel Quarneti <manuelquarneti@protonmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use tempfile::NamedTempFile;
+
+use crate::transport::{HttpError, Request, Transport};
+use crate::verify::Hasher;
+use crate::HashAlgorithm;
+
+/// Metadata that changes often, like the version manifest.
+pub const SHORT_TTL: Duration = Duration::from_secs(10 * 60);
+/// Search results and project listings.
+pub const MEDIUM_TTL: Duration = Duration::from_secs(60 * 60);
+/// Things that rarely change, like the latest Java runtime.
+pub const LONG_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Serialize, Deserialize)]
+struct CacheEntry {
+    url: String,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    /// Seconds since the Unix epoch
+    fetched_at: u64,
+}
+
+impl CacheEntry {
+    fn age(&self) -> Duration {
+        let fetched_at = UNIX_EPOCH + Duration::from_secs(self.fetched_at);
+        SystemTime::now()
+            .duration_since(fetched_at)
+            .unwrap_or_default()
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// On-disk cache for metadata JSON.
+///
+/// Responses are kept along with their `ETag` and `Last-Modified` headers. Fresh entries are
+/// served without touching the network, stale ones are revalidated with a conditional request,
+/// and if the server can't be reached the stale copy is used anyway.
+#[derive(Debug, Clone)]
+pub struct MetaCache {
+    dir: PathBuf,
+}
+
+impl MetaCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn paths(&self, url: &str) -> (PathBuf, PathBuf) {
+        let mut hasher = Hasher::new(&HashAlgorithm::Sha1);
+        hasher.update(url.as_bytes());
+        let key = hasher.finalize();
+
+        (
+            self.dir.join(format!("{key}.body")),
+            self.dir.join(format!("{key}.json")),
+        )
+    }
+
+    fn load(&self, url: &str) -> Option<(CacheEntry, PathBuf)> {
+        let (body_path, entry_path) = self.paths(url);
+
+        let entry = fs::read(entry_path).ok()?;
+        let entry = serde_json::from_slice::<CacheEntry>(&entry).ok()?;
+
+        // guard against hash collisions
+        if entry.url != url || !body_path.exists() {
+            return None;
+        }
+
+        Some((entry, body_path))
+    }
+
+    fn store(&self, entry: &CacheEntry, body: &[u8]) -> Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let (body_path, entry_path) = self.paths(&entry.url);
+
+        let mut file = NamedTempFile::new_in(&self.dir)?;
+        file.write_all(body)?;
+        file.persist(body_path)?;
+
+        let mut file = NamedTempFile::new_in(&self.dir)?;
+        serde_json::to_writer(&mut file, entry)?;
+        file.persist(entry_path)?;
+
+        Ok(())
+    }
+
+    /// Returns the cached body of `url` without going to the network, if there is one.
+    pub fn get_offline(&self, url: &str) -> Option<Vec<u8>> {
+        let (_, body_path) = self.load(url)?;
+        fs::read(body_path).ok()
+    }
+
+    /// Returns the body of `url`, going to the network only if the cached copy is older than
+    /// `ttl`.
+    pub fn get(&self, transport: &dyn Transport, url: &str, ttl: Duration) -> Result<Vec<u8>> {
+        let cached = self.load(url);
+
+        let mut request = Request::get(url);
+
+        if let Some((entry, body_path)) = &cached {
+            if entry.age() < ttl {
+                return Ok(fs::read(body_path)?);
+            }
+
+            if let Some(etag) = &entry.etag {
+                request = request.header("If-None-Match", etag);
+            }
+            if let Some(last_modified) = &entry.last_modified {
+                request = request.header("If-Modified-Since", last_modified);
+            }
+        }
+
+        let response = match transport.send(request) {
+            Ok(response) if response.status < 500 => Ok(response),
+            Ok(response) => Err(HttpError::Status {
+                url: url.to_string(),
+                code: response.status,
+            }),
+            Err(error) => Err(error),
+        };
+
+        let response = match (response, cached) {
+            (Ok(response), Some((mut entry, body_path))) if response.status == 304 => {
+                entry.fetched_at = now();
+                let body = fs::read(body_path)?;
+                self.store(&entry, &body)?;
+
+                return Ok(body);
+            }
+            (Ok(response), _) => response.error_for_status()?,
+            (Err(error), Some((_, body_path))) => {
+                println!("{}, using cached copy", error);
+
+                return Ok(fs::read(body_path)?);
+            }
+            (Err(error), None) => return Err(anyhow!(error)),
+        };
+
+        let entry = CacheEntry {
+            url: url.to_string(),
+            etag: response.header("ETag").map(str::to_string),
+            last_modified: response.header("Last-Modified").map(str::to_string),
+            fetched_at: now(),
+        };
+        let body = response.into_bytes()?;
+
+        if let Err(error) = self.store(&entry, &body) {
+            println!("Failed to cache {}: {}", url, error);
+        }
+
+        Ok(body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::endpoints::Endpoints;
+    use crate::transport::MemoryTransport;
+    use crate::Context;
+
+    const URL: &str = "https://piston-meta.mojang.com/mc/game/version_manifest_v2.json";
+
+    fn etag(value: &str) -> Vec<(String, String)> {
+        vec![("ETag".to_string(), value.to_string())]
+    }
+
+    #[test]
+    fn fresh_entries_skip_the_network() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = MetaCache::new(dir.path());
+        let transport = MemoryTransport::new();
+        transport.add_response(URL, 200, vec![], "v1");
+
+        assert_eq!(cache.get(&transport, URL, LONG_TTL).unwrap(), b"v1");
+
+        transport.add_response(URL, 200, vec![], "v2");
+
+        assert_eq!(cache.get(&transport, URL, LONG_TTL).unwrap(), b"v1");
+        assert_eq!(transport.requests().len(), 1);
+
+        // once the entry is too old the new body is fetched
+        assert_eq!(cache.get(&transport, URL, Duration::ZERO).unwrap(), b"v2");
+        assert_eq!(transport.requests().len(), 2);
+    }
+
+    #[test]
+    fn stale_entries_are_revalidated() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = MetaCache::new(dir.path());
+        let transport = MemoryTransport::new();
+        transport.add_response(URL, 200, etag("\"v1\""), "v1");
+
+        cache.get(&transport, URL, Duration::ZERO).unwrap();
+
+        transport.add_response(URL, 304, etag("\"v1\""), "");
+
+        assert_eq!(cache.get(&transport, URL, Duration::ZERO).unwrap(), b"v1");
+
+        let requests = transport.requests();
+        assert!(!requests[0]
+            .headers
+            .iter()
+            .any(|(name, _)| name == "If-None-Match"));
+        assert!(requests[1]
+            .headers
+            .contains(&("If-None-Match".to_string(), "\"v1\"".to_string())));
+    }
+
+    #[test]
+    fn stale_copy_is_used_when_the_server_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = MetaCache::new(dir.path());
+        let transport = MemoryTransport::new();
+        transport.add_response(URL, 200, vec![], "v1");
+
+        cache.get(&transport, URL, Duration::ZERO).unwrap();
+
+        transport.add_response(URL, 503, vec![], "");
+        assert_eq!(cache.get(&transport, URL, Duration::ZERO).unwrap(), b"v1");
+
+        transport.add_error(URL, "connection reset");
+        assert_eq!(cache.get(&transport, URL, Duration::ZERO).unwrap(), b"v1");
+
+        // nothing to fall back to
+        let other = "https://piston-meta.mojang.com/other.json";
+        transport.add_error(other, "connection reset");
+        assert!(cache.get(&transport, other, Duration::ZERO).is_err());
+    }
+
+    #[test]
+    fn offline_serves_the_cached_copy() {
+        let dir = tempfile::tempdir().unwrap();
+        let transport = Arc::new(MemoryTransport::new());
+        transport.add_response(URL, 200, vec![], "v1");
+
+        let ctx = Context::with_transport(transport.clone(), Endpoints::default())
+            .with_cache(MetaCache::new(dir.path()));
+
+        ctx.get_cached(URL, Duration::ZERO).unwrap();
+        ctx.set_offline(true);
+
+        assert_eq!(ctx.get_cached(URL, Duration::ZERO).unwrap(), b"v1");
+        assert_eq!(transport.requests().len(), 1);
+        assert!(ctx
+            .get_cached("https://piston-meta.mojang.com/other.json", LONG_TTL)
+            .is_err());
+    }
+}
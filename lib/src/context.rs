@@ -0,0 +1,383 @@
+// SPDX-FileCopyrightText: 2023 Manuel Quarneti <manuelquarneti@protonmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+use oauth2::http::{header::HeaderName, HeaderMap, HeaderValue, StatusCode};
+use oauth2::{HttpRequest, HttpResponse};
+use serde::de::DeserializeOwned;
+
+use crate::cache::MetaCache;
+use crate::endpoints::Endpoints;
+use crate::paths::CACHE_DIR;
+use crate::proxy::Proxy;
+use crate::settings::Settings;
+use crate::throttle::Bandwidth;
+use crate::transport::{HttpError, Method, Request, Response, Transport, UreqTransport};
+
+/// Everything the library needs to talk to the outside world.
+///
+/// It's cheap to clone, so every long-running operation can own a copy.
+#[derive(Clone)]
+pub struct Context {
+    transport: Arc<dyn Transport>,
+    endpoints: Arc<Endpoints>,
+    proxy: Option<Arc<Proxy>>,
+    cache: MetaCache,
+    bandwidth: Bandwidth,
+    /// Shared between clones, so that detecting a lost connection affects every copy
+    offline: Arc<AtomicBool>,
+    /// Offline mode was turned on in the settings
+    forced_offline: bool,
+}
+
+impl Default for Context {
+    fn default() -> Self {
+        Self::new(&Settings::default())
+    }
+}
+
+impl Context {
+    pub fn new(settings: &Settings) -> Self {
+        let proxy = settings.proxy.resolve();
+
+        let transport = match proxy.clone().map(UreqTransport::with_proxy) {
+            Some(Ok(transport)) => transport,
+            Some(Err(error)) => {
+                println!("Invalid proxy, connecting directly: {}", error);
+                UreqTransport::default()
+            }
+            None => UreqTransport::default(),
+        };
+
+        let mut ctx = Self::with_transport(Arc::new(transport), settings.endpoints.clone());
+        ctx.proxy = proxy.map(Arc::new);
+        ctx.set_limits(settings);
+        ctx.forced_offline = settings.offline;
+        ctx.set_offline(settings.offline);
+        ctx
+    }
+
+    /// Applies changed settings.
+    ///
+    /// The bandwidth limits change in place, so running downloads and games keep sharing them, and
+    /// a lost connection that was detected stays detected.
+    pub fn update_settings(&mut self, settings: &Settings) {
+        let detected_offline = self.is_offline() && !self.forced_offline;
+        self.set_limits(settings);
+
+        *self = Self {
+            bandwidth: self.bandwidth.clone(),
+            offline: Arc::clone(&self.offline),
+            ..Self::new(settings)
+        };
+        self.set_offline(detected_offline);
+    }
+
+    fn set_limits(&self, settings: &Settings) {
+        self.bandwidth.set_limits(
+            settings.max_download_speed.map(|speed| speed * 1000),
+            settings
+                .max_background_download_speed
+                .map(|speed| speed * 1000),
+        );
+    }
+
+    pub fn with_transport(transport: Arc<dyn Transport>, endpoints: Endpoints) -> Self {
+        Self {
+            transport,
+            endpoints: Arc::new(endpoints),
+            proxy: None,
+            cache: MetaCache::new(&*CACHE_DIR),
+            bandwidth: Bandwidth::default(),
+            offline: Arc::new(AtomicBool::new(false)),
+            forced_offline: false,
+        }
+    }
+
+    pub fn with_cache(mut self, cache: MetaCache) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    pub fn with_bandwidth(mut self, bandwidth: Bandwidth) -> Self {
+        self.bandwidth = bandwidth;
+        self
+    }
+
+    pub fn endpoints(&self) -> &Endpoints {
+        &self.endpoints
+    }
+
+    /// The proxy network traffic goes through, if any.
+    pub fn proxy(&self) -> Option<&Proxy> {
+        self.proxy.as_deref()
+    }
+
+    /// The download rate limit shared by every clone.
+    pub fn bandwidth(&self) -> &Bandwidth {
+        &self.bandwidth
+    }
+
+    pub fn is_offline(&self) -> bool {
+        self.offline.load(Ordering::Relaxed)
+    }
+
+    /// Switches offline mode on or off, unless it was forced on in the settings.
+    pub fn set_offline(&self, offline: bool) {
+        self.offline
+            .store(offline || self.forced_offline, Ordering::Relaxed);
+    }
+
+    /// Checks whether the network is reachable and goes offline if it isn't.
+    ///
+    /// Returns whether the launcher is offline.
+    pub fn detect_offline(&self) -> bool {
+        if self.forced_offline {
+            return true;
+        }
+
+        let url = format!(
+            "{}/mc/game/version_manifest_v2.json",
+            self.endpoints.piston_meta
+        );
+        let reachable = self
+            .transport
+            .send(Request::new(Method::Head, &url))
+            .is_ok();
+
+        if !reachable {
+            println!("Network unreachable, switching to offline mode");
+        }
+
+        self.set_offline(!reachable);
+        !reachable
+    }
+
+    fn check_online(&self, url: &str) -> Result<(), HttpError> {
+        if self.is_offline() {
+            Err(HttpError::Offline {
+                url: url.to_string(),
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Sends a request to the configured endpoints, turning error statuses into errors.
+    pub fn send(&self, mut request: Request) -> Result<Response, HttpError> {
+        request.url = self.endpoints.rewrite(&request.url);
+        self.send_resolved(request)
+    }
+
+    /// Like [`Context::send`], for URLs that already went through [`Endpoints::resolve`].
+    pub(crate) fn send_resolved(&self, request: Request) -> Result<Response, HttpError> {
+        self.check_online(&request.url)?;
+        self.transport.send(request)?.error_for_status()
+    }
+
+    /// Sends a GET request, falling back to the mirrors if the primary URL fails.
+    pub fn get(&self, url: &str) -> Result<Response, HttpError> {
+        let mut last_error = None;
+
+        for url in self.endpoints.resolve(url) {
+            match self.send_resolved(Request::get(&url)) {
+                Ok(response) => return Ok(response),
+                Err(error) => {
+                    println!("request failed: {}: {}", url, error);
+                    last_error = Some(error);
+                }
+            }
+        }
+
+        Err(last_error.expect("resolve returns at least one URL"))
+    }
+
+    pub fn get_json<T: DeserializeOwned>(&self, url: &str) -> Result<T> {
+        let json = self.get(url)?.into_json()?;
+
+        Ok(json)
+    }
+
+    /// Like [`Context::get`], but goes through the metadata cache.
+    pub fn get_cached(&self, url: &str, ttl: Duration) -> Result<Vec<u8>> {
+        let mut last_error = None;
+
+        for url in self.endpoints.resolve(url) {
+            match self.get_cached_resolved(&url, ttl) {
+                Ok(bytes) => return Ok(bytes),
+                Err(error) => {
+                    println!("request failed: {}: {}", url, error);
+                    last_error = Some(error);
+                }
+            }
+        }
+
+        Err(last_error.expect("resolve returns at least one URL"))
+    }
+
+    /// Like [`Context::get_cached`], for URLs that already went through [`Endpoints::resolve`].
+    pub(crate) fn get_cached_resolved(&self, url: &str, ttl: Duration) -> Result<Vec<u8>> {
+        if self.is_offline() {
+            return self.cache.get_offline(url).ok_or_else(|| {
+                anyhow!(HttpError::Offline {
+                    url: url.to_string()
+                })
+            });
+        }
+
+        self.cache.get(&*self.transport, url, ttl)
+    }
+
+    pub fn get_json_cached<T: DeserializeOwned>(&self, url: &str, ttl: Duration) -> Result<T> {
+        let json = serde_json::from_slice(&self.get_cached(url, ttl)?)?;
+
+        Ok(json)
+    }
+
+    pub fn post_json<T: DeserializeOwned>(&self, url: &str, body: &serde_json::Value) -> Result<T> {
+        let json = self.send(Request::post(url).json(body))?.into_json()?;
+
+        Ok(json)
+    }
+
+    /// HTTP client for the `oauth2` crate, going through the same transport.
+    pub fn oauth_request(&self, request: HttpRequest) -> Result<HttpResponse, HttpError> {
+        let method = if request.method == oauth2::http::Method::POST {
+            Method::Post
+        } else {
+            Method::Get
+        };
+
+        let url = self.endpoints.rewrite(request.url.as_str());
+        self.check_online(&url)?;
+
+        let mut req = Request::new(method, &url);
+        for (name, value) in &request.headers {
+            if let Ok(value) = value.to_str() {
+                req = req.header(name.as_str(), value);
+            }
+        }
+        if method == Method::Post {
+            req.body = Some(request.body);
+        }
+
+        // oauth2 parses error responses on its own, so they're passed through as they are
+        let response = self.transport.send(req)?;
+
+        let invalid = |message: String| HttpError::Transport {
+            url: url.to_owned(),
+            message,
+        };
+
+        let status_code =
+            StatusCode::from_u16(response.status).map_err(|error| invalid(error.to_string()))?;
+
+        let mut headers = HeaderMap::new();
+        for (name, value) in &response.headers {
+            if let (Ok(name), Ok(value)) = (
+                HeaderName::from_bytes(name.as_bytes()),
+                HeaderValue::from_str(value),
+            ) {
+                headers.append(name, value);
+            }
+        }
+
+        let body = response
+            .into_bytes()
+            .map_err(|error| invalid(error.to_string()))?;
+
+        Ok(HttpResponse {
+            status_code,
+            headers,
+            body,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::endpoints::UrlRule;
+    use crate::transport::MemoryTransport;
+
+    fn rule(prefix: &str, replacement: &str) -> UrlRule {
+        UrlRule {
+            prefix: prefix.to_string(),
+            replacement: replacement.to_string(),
+        }
+    }
+
+    #[test]
+    fn cached_gets_fall_back_to_the_mirrors() {
+        let dir = tempfile::tempdir().unwrap();
+        let transport = Arc::new(MemoryTransport::new());
+        transport.add_error(
+            "https://meta.example.com/mc/game/version_manifest_v2.json",
+            "connection refused",
+        );
+        transport.add_json(
+            "https://mirror.example.com/mc/game/version_manifest_v2.json",
+            &json!({ "versions": [] }),
+        );
+
+        let endpoints = Endpoints {
+            piston_meta: "https://meta.example.com".to_string(),
+            mirrors: vec![rule(
+                "https://piston-meta.mojang.com/",
+                "https://mirror.example.com/",
+            )],
+            ..Default::default()
+        };
+        let ctx = Context::with_transport(transport.clone(), endpoints)
+            .with_cache(MetaCache::new(dir.path()));
+
+        let url = format!(
+            "{}/mc/game/version_manifest_v2.json",
+            ctx.endpoints().piston_meta
+        );
+        let json = ctx
+            .get_json_cached::<serde_json::Value>(&url, Duration::ZERO)
+            .unwrap();
+
+        assert_eq!(json, json!({ "versions": [] }));
+        assert_eq!(transport.requests().len(), 2);
+    }
+
+    #[test]
+    fn posts_are_rewritten() {
+        let transport = Arc::new(MemoryTransport::new());
+        transport.add_json("https://proxy.example.com/login", &json!({ "ok": true }));
+
+        let endpoints = Endpoints {
+            rewrites: vec![rule(
+                "https://api.minecraftservices.com/authentication/",
+                "https://proxy.example.com/",
+            )],
+            ..Default::default()
+        };
+        let ctx = Context::with_transport(transport.clone(), endpoints);
+
+        let json = ctx
+            .post_json::<serde_json::Value>(
+                "https://api.minecraftservices.com/authentication/login",
+                &json!({}),
+            )
+            .unwrap();
+
+        assert_eq!(json, json!({ "ok": true }));
+        assert_eq!(
+            transport.requests()[0].url,
+            "https://proxy.example.com/login"
+        );
+    }
+}
@@ -0,0 +1,107 @@
+// SPDX-FileCopyrightText: 2023 Manuel Quarneti <manuelquarneti@protonmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    sync::{Arc, Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::DownloadError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum RunState {
+    #[default]
+    Running,
+    Paused,
+    Cancelled,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    state: Mutex<RunState>,
+    changed: Condvar,
+}
+
+/// Pauses, resumes or cancels a running download queue.
+///
+/// Clones control the same queue. Workers stop at the next chunk, so pausing keeps partial files
+/// around while cancelling deletes them.
+#[derive(Debug, Clone, Default)]
+pub struct DownloadControl {
+    inner: Arc<Inner>,
+}
+
+impl DownloadControl {
+    fn set(&self, state: RunState) {
+        let mut current = self.inner.state.lock().unwrap();
+
+        // there's no coming back from a cancel
+        if *current != RunState::Cancelled {
+            *current = state;
+            self.inner.changed.notify_all();
+        }
+    }
+
+    fn get(&self) -> RunState {
+        *self.inner.state.lock().unwrap()
+    }
+
+    pub fn pause(&self) {
+        self.set(RunState::Paused);
+    }
+
+    pub fn resume(&self) {
+        self.set(RunState::Running);
+    }
+
+    pub fn cancel(&self) {
+        self.set(RunState::Cancelled);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.get() == RunState::Paused
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.get() == RunState::Cancelled
+    }
+
+    /// Blocks while paused, and fails once cancelled.
+    pub(crate) fn checkpoint(&self) -> Result<(), DownloadError> {
+        let state = self.inner.state.lock().unwrap();
+        let state = self
+            .inner
+            .changed
+            .wait_while(state, |state| *state == RunState::Paused)
+            .unwrap();
+
+        match *state {
+            RunState::Cancelled => Err(DownloadError::Cancelled),
+            _ => Ok(()),
+        }
+    }
+
+    /// Sleeps for `duration`, waking up early if cancelled.
+    pub(crate) fn sleep(&self, duration: Duration) -> Result<(), DownloadError> {
+        let deadline = Instant::now() + duration;
+        let mut state = self.inner.state.lock().unwrap();
+
+        loop {
+            if *state == RunState::Cancelled {
+                return Err(DownloadError::Cancelled);
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(());
+            }
+
+            state = self
+                .inner
+                .changed
+                .wait_timeout(state, deadline - now)
+                .unwrap()
+                .0;
+        }
+    }
+}
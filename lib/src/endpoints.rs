@@ -0,0 +1,197 @@
+// SPDX-FileCopyrightText: 2023 Manuel Quarneti <manuelquarneti@protonmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use serde::{Deserialize, Serialize};
+
+const PISTON_META: &str = "https://piston-meta.mojang.com";
+const PISTON_DATA: &str = "https://piston-data.mojang.com";
+const RESOURCES: &str = "https://resources.download.minecraft.net";
+const LIBRARIES: &str = "https://libraries.minecraft.net";
+const ADOPTIUM_API: &str = "https://api.adoptium.net";
+const FABRIC_META: &str = "https://meta.fabricmc.net";
+const MODRINTH_API: &str = "https://api.modrinth.com";
+const GITHUB_API: &str = "https://api.github.com";
+const GITHUB: &str = "https://github.com";
+
+/// Replaces `prefix` with `replacement` at the start of matching URLs.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UrlRule {
+    pub prefix: String,
+    pub replacement: String,
+}
+
+impl UrlRule {
+    fn apply(&self, url: &str) -> Option<String> {
+        url.strip_prefix(&self.prefix)
+            .map(|rest| format!("{}{}", self.replacement, rest))
+    }
+}
+
+/// Base URLs of every remote service the launcher talks to.
+///
+/// URLs found in remote metadata (e.g. library downloads in a version JSON) are rewritten too,
+/// so pointing a service somewhere else also affects them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Endpoints {
+    pub piston_meta: String,
+    pub piston_data: String,
+    pub resources: String,
+    pub libraries: String,
+    pub adoptium_api: String,
+    pub fabric_meta: String,
+    pub modrinth_api: String,
+    pub github_api: String,
+    pub github: String,
+
+    /// Applied in order, the first matching rule wins.
+    pub rewrites: Vec<UrlRule>,
+
+    /// Every matching rule adds a fallback URL, tried in order when the primary one fails.
+    pub mirrors: Vec<UrlRule>,
+}
+
+impl Default for Endpoints {
+    fn default() -> Self {
+        Self {
+            piston_meta: PISTON_META.to_string(),
+            piston_data: PISTON_DATA.to_string(),
+            resources: RESOURCES.to_string(),
+            libraries: LIBRARIES.to_string(),
+            adoptium_api: ADOPTIUM_API.to_string(),
+            fabric_meta: FABRIC_META.to_string(),
+            modrinth_api: MODRINTH_API.to_string(),
+            github_api: GITHUB_API.to_string(),
+            github: GITHUB.to_string(),
+            rewrites: Vec::new(),
+            mirrors: Vec::new(),
+        }
+    }
+}
+
+impl Endpoints {
+    fn services(&self) -> [(&'static str, &str); 9] {
+        [
+            (PISTON_META, &self.piston_meta),
+            (PISTON_DATA, &self.piston_data),
+            (RESOURCES, &self.resources),
+            (LIBRARIES, &self.libraries),
+            (ADOPTIUM_API, &self.adoptium_api),
+            (FABRIC_META, &self.fabric_meta),
+            (MODRINTH_API, &self.modrinth_api),
+            (GITHUB_API, &self.github_api),
+            (GITHUB, &self.github),
+        ]
+    }
+
+    /// Moves a URL from a configured service base back to the default one.
+    ///
+    /// URLs built from the configured bases are matched against the mirror rules like this, so the
+    /// rules can always be written for the default hosts.
+    fn original(&self, url: &str) -> String {
+        for (default, configured) in self.services() {
+            if default == configured {
+                continue;
+            }
+
+            if let Some(rest) = url.strip_prefix(configured.trim_end_matches('/')) {
+                return format!("{}{}", default, rest);
+            }
+        }
+
+        url.to_string()
+    }
+
+    /// Moves a URL to the configured service base and applies the first matching rewrite rule.
+    pub fn rewrite(&self, url: &str) -> String {
+        let mut url = self.original(url);
+
+        for (default, configured) in self.services() {
+            if default == configured {
+                continue;
+            }
+
+            if let Some(rest) = url.strip_prefix(default) {
+                url = format!("{}{}", configured.trim_end_matches('/'), rest);
+                break;
+            }
+        }
+
+        self.rewrites
+            .iter()
+            .find_map(|rule| rule.apply(&url))
+            .unwrap_or(url)
+    }
+
+    /// Returns the URLs to try for a download, primary first, without duplicates.
+    ///
+    /// Mirror rules match the URL on the default service host, wherever the service was moved to.
+    pub fn resolve(&self, url: &str) -> Vec<String> {
+        let original = self.original(url);
+        let mut urls = vec![self.rewrite(&original)];
+
+        for mirror in self.mirrors.iter().filter_map(|rule| rule.apply(&original)) {
+            if !urls.contains(&mirror) {
+                urls.push(mirror);
+            }
+        }
+
+        urls
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mirror(prefix: &str, replacement: &str) -> UrlRule {
+        UrlRule {
+            prefix: prefix.to_string(),
+            replacement: replacement.to_string(),
+        }
+    }
+
+    #[test]
+    fn mirrors_match_the_default_host_of_a_moved_service() {
+        let endpoints = Endpoints {
+            resources: "https://assets.example.com/".to_string(),
+            mirrors: vec![mirror(
+                "https://resources.download.minecraft.net/",
+                "https://mirror.example.com/resources/",
+            )],
+            ..Default::default()
+        };
+
+        // asset URLs are built from the configured base
+        let url = format!("{}/ab/abcdef", endpoints.resources.trim_end_matches('/'));
+
+        assert_eq!(
+            endpoints.resolve(&url),
+            [
+                "https://assets.example.com/ab/abcdef",
+                "https://mirror.example.com/resources/ab/abcdef",
+            ]
+        );
+        assert_eq!(
+            endpoints.resolve("https://resources.download.minecraft.net/ab/abcdef"),
+            endpoints.resolve(&url)
+        );
+    }
+
+    #[test]
+    fn rewriting_twice_changes_nothing() {
+        let endpoints = Endpoints {
+            piston_meta: "https://meta.example.com".to_string(),
+            ..Default::default()
+        };
+
+        let url =
+            endpoints.rewrite("https://piston-meta.mojang.com/mc/game/version_manifest_v2.json");
+
+        assert_eq!(
+            url,
+            "https://meta.example.com/mc/game/version_manifest_v2.json"
+        );
+        assert_eq!(endpoints.rewrite(&url), url);
+    }
+}
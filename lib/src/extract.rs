@@ -0,0 +1,493 @@
+// SPDX-FileCopyrightText: 2023 Manuel Quarneti <manuelquarneti@protonmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    fs::{self, File},
+    io::{self, BufReader, Read, Seek},
+    path::{Component, Path, PathBuf},
+};
+
+use flate2::bufread::GzDecoder;
+use serde::{Deserialize, Serialize};
+use tar::{Archive, EntryType};
+use zip::ZipArchive;
+
+use crate::DownloadError;
+
+const S_IFMT: u32 = 0o170000;
+const S_IFLNK: u32 = 0o120000;
+
+/// How many symlinks a path can go through, like `MAXSYMLINKS` on Linux
+const MAX_SYMLINK_HOPS: usize = 40;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Zip,
+    Tar,
+    TarGz,
+    TarXz,
+}
+
+impl ArchiveFormat {
+    /// Detects the format from the magic bytes at the start of the file.
+    pub fn detect(path: &Path) -> io::Result<Option<Self>> {
+        let mut header = Vec::with_capacity(262);
+        File::open(path)?.take(262).read_to_end(&mut header)?;
+
+        let format = if header.starts_with(b"PK\x03\x04") || header.starts_with(b"PK\x05\x06") {
+            Some(ArchiveFormat::Zip)
+        } else if header.starts_with(&[0x1f, 0x8b]) {
+            Some(ArchiveFormat::TarGz)
+        } else if header.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Some(ArchiveFormat::TarXz)
+        } else if header.len() >= 262 && &header[257..262] == b"ustar" {
+            Some(ArchiveFormat::Tar)
+        } else {
+            None
+        };
+
+        Ok(format)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExtractOptions {
+    /// Drop the directory every entry is in, like `tar --strip-components=1`
+    pub strip_top_level: bool,
+}
+
+/// Links are created after every regular file, so nothing is ever written through them.
+enum Link {
+    Hard { path: PathBuf, target: PathBuf },
+    Symbolic { path: PathBuf, target: PathBuf },
+}
+
+fn invalid(archive: &Path, message: impl std::fmt::Display) -> DownloadError {
+    DownloadError::InvalidArchive(format!("{}: {}", archive.display(), message))
+}
+
+/// Extracts `archive` into the `dest` directory, which must not exist yet.
+///
+/// Everything is unpacked into a staging directory next to `dest` first, and only renamed into
+/// place once extraction succeeded, so `dest` either doesn't exist or is complete.
+pub fn extract(archive: &Path, dest: &Path, options: ExtractOptions) -> Result<(), DownloadError> {
+    let format = ArchiveFormat::detect(archive)?
+        .ok_or_else(|| DownloadError::UnsupportedArchive(archive.display().to_string()))?;
+
+    let parent = dest
+        .parent()
+        .ok_or_else(|| invalid(dest, "destination has no parent directory"))?;
+    fs::create_dir_all(parent)?;
+
+    // removed on drop if anything goes wrong
+    let staging = tempfile::Builder::new()
+        .prefix(".extracting-")
+        .tempdir_in(parent)?;
+
+    let mut reader = BufReader::new(File::open(archive)?);
+    let mut extractor = Extractor {
+        archive,
+        dest: staging.path(),
+        options,
+        exclude: &[],
+        links: Vec::new(),
+    };
+
+    match format {
+        ArchiveFormat::Zip => extractor.zip(reader)?,
+        ArchiveFormat::Tar => extractor.tar(reader)?,
+        ArchiveFormat::TarGz => extractor.tar(GzDecoder::new(reader))?,
+        ArchiveFormat::TarXz => {
+            // lzma-rs can't decompress while reading, so go through a temporary file
+            let mut tar = tempfile::tempfile_in(parent)?;
+            lzma_rs::xz_decompress(&mut reader, &mut tar)
+                .map_err(|error| invalid(archive, error))?;
+            tar.rewind()?;
+
+            extractor.tar(BufReader::new(tar))?;
+        }
+    }
+
+    extractor.create_links()?;
+
+    fs::rename(staging.path(), dest)?;
+
+    Ok(())
+}
+
+/// Extracts the zip `archive` into the existing `dest` directory, merging it with what's there.
+///
+/// Entries whose names start with any of `exclude` are skipped, like `META-INF/` when
+/// unpacking native libraries.
+pub fn extract_zip_into(
+    archive: &Path,
+    dest: &Path,
+    exclude: &[String],
+) -> Result<(), DownloadError> {
+    let mut extractor = Extractor {
+        archive,
+        dest,
+        options: ExtractOptions::default(),
+        exclude,
+        links: Vec::new(),
+    };
+
+    extractor.zip(BufReader::new(File::open(archive)?))?;
+    extractor.create_links()
+}
+
+struct Extractor<'a> {
+    archive: &'a Path,
+    dest: &'a Path,
+    options: ExtractOptions,
+    /// Name prefixes of the entries to skip
+    exclude: &'a [String],
+    links: Vec<Link>,
+}
+
+impl Extractor<'_> {
+    /// Maps an entry name to a path relative to the destination.
+    ///
+    /// Returns `None` for excluded entries, and for those that have nothing left after stripping
+    /// the top-level directory.
+    fn entry_path(&self, name: &Path) -> Result<Option<PathBuf>, DownloadError> {
+        let name_str = name.to_string_lossy();
+        if self
+            .exclude
+            .iter()
+            .any(|prefix| name_str.starts_with(prefix.as_str()))
+        {
+            return Ok(None);
+        }
+
+        let mut components = Vec::new();
+
+        for component in name.components() {
+            match component {
+                Component::Normal(component) => components.push(component),
+                Component::CurDir => {}
+                Component::ParentDir | Component::RootDir | Component::Prefix(_) => {
+                    return Err(invalid(
+                        self.archive,
+                        format!("entry escapes the destination: {}", name.display()),
+                    ));
+                }
+            }
+        }
+
+        if self.options.strip_top_level && !components.is_empty() {
+            components.remove(0);
+        }
+
+        if components.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(components.iter().collect()))
+    }
+
+    /// Makes sure a symlink at `path` pointing to `target` stays inside the destination.
+    ///
+    /// The target is resolved the way the OS would, going through the other symlinks of the
+    /// archive, so `a -> .` and `b -> a/..` can't be combined to escape.
+    fn check_symlink(
+        &self,
+        symlinks: &HashMap<&Path, &Path>,
+        path: &Path,
+        target: &Path,
+    ) -> Result<(), DownloadError> {
+        let mut hops = 0;
+        let resolved = path
+            .parent()
+            .and_then(|dir| resolve(symlinks, Vec::new(), dir, &mut hops))
+            .and_then(|dir| resolve(symlinks, dir, target, &mut hops));
+
+        if resolved.is_none() {
+            return Err(invalid(
+                self.archive,
+                format!(
+                    "symlink escapes the destination: {} -> {}",
+                    path.display(),
+                    target.display()
+                ),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn write_file(
+        &self,
+        path: &Path,
+        reader: &mut impl Read,
+        mode: Option<u32>,
+    ) -> Result<(), DownloadError> {
+        let out = self.dest.join(path);
+
+        if let Some(parent) = out.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut file = File::create(&out)?;
+        io::copy(reader, &mut file)?;
+
+        #[cfg(unix)]
+        if let Some(mode) = mode {
+            use std::os::unix::fs::PermissionsExt;
+
+            // no setuid, setgid or sticky bits
+            fs::set_permissions(&out, fs::Permissions::from_mode(mode & 0o777))?;
+        }
+
+        #[cfg(not(unix))]
+        let _ = mode;
+
+        Ok(())
+    }
+
+    fn zip(&mut self, reader: impl Read + Seek) -> Result<(), DownloadError> {
+        let mut archive = ZipArchive::new(reader)?;
+
+        for i in 0..archive.len() {
+            let mut entry = archive.by_index(i)?;
+
+            let Some(path) = self.entry_path(Path::new(entry.name()))? else {
+                continue;
+            };
+
+            let mode = entry.unix_mode();
+
+            if entry.is_dir() {
+                fs::create_dir_all(self.dest.join(path))?;
+            } else if mode.is_some_and(|mode| mode & S_IFMT == S_IFLNK) {
+                let mut target = String::new();
+                entry.read_to_string(&mut target)?;
+                let target = PathBuf::from(target);
+
+                self.links.push(Link::Symbolic { path, target });
+            } else {
+                self.write_file(&path, &mut entry, mode)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn tar(&mut self, reader: impl Read) -> Result<(), DownloadError> {
+        let mut archive = Archive::new(reader);
+
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+
+            let Some(path) = self.entry_path(&entry.path()?)? else {
+                continue;
+            };
+
+            match entry.header().entry_type() {
+                EntryType::Directory => fs::create_dir_all(self.dest.join(path))?,
+                EntryType::Regular | EntryType::Continuous | EntryType::GNUSparse => {
+                    let mode = entry.header().mode().ok();
+                    self.write_file(&path, &mut entry, mode)?;
+                }
+                EntryType::Symlink => {
+                    let target = entry
+                        .link_name()?
+                        .ok_or_else(|| invalid(self.archive, "symlink without a target"))?
+                        .into_owned();
+
+                    self.links.push(Link::Symbolic { path, target });
+                }
+                EntryType::Link => {
+                    let target = entry
+                        .link_name()?
+                        .ok_or_else(|| invalid(self.archive, "hard link without a target"))?;
+
+                    let target = self.entry_path(&target)?.ok_or_else(|| {
+                        invalid(self.archive, "hard link to the top-level directory")
+                    })?;
+
+                    self.links.push(Link::Hard { path, target });
+                }
+                // pax headers and the like, handled by the tar crate
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    fn create_links(&mut self) -> Result<(), DownloadError> {
+        // hard links first, so they can't go through a symlink
+        self.links
+            .sort_by_key(|link| matches!(link, Link::Symbolic { .. }));
+
+        // every symlink is checked before any is created, as they can point through each other
+        let symlinks = self
+            .links
+            .iter()
+            .filter_map(|link| match link {
+                Link::Symbolic { path, target } => Some((path.as_path(), target.as_path())),
+                Link::Hard { .. } => None,
+            })
+            .collect::<HashMap<_, _>>();
+
+        for (path, target) in &symlinks {
+            self.check_symlink(&symlinks, path, target)?;
+        }
+
+        for link in &self.links {
+            match link {
+                Link::Hard { path, target } => {
+                    let out = self.dest.join(path);
+                    if let Some(parent) = out.parent() {
+                        fs::create_dir_all(parent)?;
+                    }
+
+                    fs::hard_link(self.dest.join(target), out)?;
+                }
+                Link::Symbolic { path, target } => {
+                    let out = self.dest.join(path);
+                    if let Some(parent) = out.parent() {
+                        fs::create_dir_all(parent)?;
+                    }
+
+                    #[cfg(unix)]
+                    std::os::unix::fs::symlink(target, out)?;
+
+                    #[cfg(not(unix))]
+                    println!("skipping symlink {} -> {}", out.display(), target.display());
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Walks `path` from the `dir` components of the destination, following `symlinks` along the way.
+///
+/// Returns `None` if it leaves the destination or goes through too many symlinks.
+fn resolve(
+    symlinks: &HashMap<&Path, &Path>,
+    mut dir: Vec<OsString>,
+    path: &Path,
+    hops: &mut usize,
+) -> Option<Vec<OsString>> {
+    for component in path.components() {
+        match component {
+            Component::Normal(component) => {
+                dir.push(component.to_os_string());
+
+                if let Some(target) = symlinks.get(dir.iter().collect::<PathBuf>().as_path()) {
+                    *hops += 1;
+                    if *hops > MAX_SYMLINK_HOPS {
+                        return None;
+                    }
+
+                    dir.pop();
+                    dir = resolve(symlinks, dir, target, hops)?;
+                }
+            }
+            Component::CurDir => {}
+            Component::ParentDir => {
+                dir.pop()?;
+            }
+            Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+
+    Some(dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tar::{Builder, Header};
+    use tempfile::TempDir;
+
+    /// A tar archive with a `dir/file` and the given symlinks.
+    fn archive(dir: &TempDir, symlinks: &[(&str, &str)]) -> PathBuf {
+        let path = dir.path().join("archive.tar");
+        let mut builder = Builder::new(File::create(&path).unwrap());
+
+        let mut header = Header::new_gnu();
+        header.set_size(5);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, "dir/file", &b"hello"[..])
+            .unwrap();
+
+        for (path, target) in symlinks {
+            let mut header = Header::new_gnu();
+            header.set_entry_type(EntryType::Symlink);
+            header.set_size(0);
+            builder.append_link(&mut header, path, target).unwrap();
+        }
+
+        builder.finish().unwrap();
+        path
+    }
+
+    fn extract_symlinks(symlinks: &[(&str, &str)]) -> Result<PathBuf, DownloadError> {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = archive(&dir, symlinks);
+        let dest = dir.path().join("out");
+
+        extract(&archive, &dest, ExtractOptions::default())?;
+
+        // keep the directory around for the caller to look into
+        Ok(dir.into_path().join("out"))
+    }
+
+    #[test]
+    fn extracts_symlinks_inside_the_destination() {
+        let dest = extract_symlinks(&[("dir/link", "file"), ("up", "dir/../dir")]).unwrap();
+
+        assert_eq!(fs::read(dest.join("dir/link")).unwrap(), b"hello");
+        assert_eq!(fs::read(dest.join("up/file")).unwrap(), b"hello");
+
+        fs::remove_dir_all(dest.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn rejects_symlinks_out_of_the_destination() {
+        for symlinks in [
+            &[("link", "..")][..],
+            &[("link", "/etc/passwd")],
+            &[("dir/link", "../../x")],
+        ] {
+            assert!(
+                matches!(
+                    extract_symlinks(symlinks),
+                    Err(DownloadError::InvalidArchive(_))
+                ),
+                "{:?}",
+                symlinks
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_symlinks_escaping_through_other_symlinks() {
+        for symlinks in [
+            &[("a", "."), ("b", "a/..")][..],
+            // the order in the archive doesn't matter
+            &[("b", "a/.."), ("a", ".")],
+            &[("a", "dir/.."), ("dir/b", "../a/..")],
+            &[("a", "b"), ("b", "a")],
+        ] {
+            assert!(
+                matches!(
+                    extract_symlinks(symlinks),
+                    Err(DownloadError::InvalidArchive(_))
+                ),
+                "{:?}",
+                symlinks
+            );
+        }
+    }
+}
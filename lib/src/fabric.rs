@@ -1,14 +1,15 @@
 // SPDX-FileCopyrightText: 2023 Manuel Quarneti <manuelquarneti@protonmail.com>
 // SPDX-License-Identifier: GPL-3.0-only
 
-use std::path::PathBuf;
+use std::{fs::File, io::BufReader, path::PathBuf};
 
 use anyhow::Result;
 use serde::Deserialize;
 
 use crate::instances::Instance;
-use crate::paths::LIBRARIES_DIR;
-use crate::{DownloadItem, AGENT};
+use crate::paths::{LIBRARIES_DIR, META_DIR};
+use crate::tasks::{Slot, TaskGraph, TaskId};
+use crate::{Context, DownloadItem};
 
 #[derive(Deserialize)]
 struct FabricLibrary {
@@ -45,23 +46,94 @@ struct FabricMeta {
     main_class: String,
 }
 
-fn download(minecraft_version: &str, fabric_version: &str) -> Result<Vec<DownloadItem>> {
-    let url = format!("https://meta.fabricmc.net/v2/versions/loader/{minecraft_version}/{fabric_version}/profile/json", );
-    let meta = AGENT.get(&url).call()?.into_json::<FabricMeta>()?;
+fn get_profile_path(minecraft_version: &str, fabric_version: &str) -> PathBuf {
+    META_DIR
+        .join("fabric")
+        .join(minecraft_version)
+        .join(format!("{}.json", fabric_version))
+}
+
+/// The libraries of an installed loader, read from its saved profile.
+pub(crate) fn get_installed_files(
+    minecraft_version: &str,
+    fabric_version: &str,
+) -> Result<Vec<PathBuf>> {
+    let file = File::open(get_profile_path(minecraft_version, fabric_version))?;
+    let meta = serde_json::from_reader::<_, FabricMeta>(BufReader::new(file))?;
+
+    Ok(meta
+        .libraries
+        .iter()
+        .map(FabricLibrary::get_full_path)
+        .collect())
+}
+
+/// Downloads the loader profile to `path`, where it's kept to know which libraries are still in use.
+fn fetch_meta(
+    ctx: &Context,
+    minecraft_version: &str,
+    fabric_version: &str,
+    path: PathBuf,
+) -> Result<FabricMeta> {
+    let url = format!(
+        "{}/v2/versions/loader/{minecraft_version}/{fabric_version}/profile/json",
+        ctx.endpoints().fabric_meta
+    );
+
+    DownloadItem {
+        url,
+        mirrors: vec![],
+        path,
+        hash: None,
+        size: None,
+        extract: None,
+    }
+    .download_json(ctx)
+}
+
+/// The libraries of a loader version, to install or repair.
+pub(crate) fn get_download_items(
+    ctx: &Context,
+    minecraft_version: &str,
+    fabric_version: &str,
+) -> Result<Vec<DownloadItem>> {
+    let path = get_profile_path(minecraft_version, fabric_version);
+    let meta = fetch_meta(ctx, minecraft_version, fabric_version, path)?;
 
     meta.libraries
         .into_iter()
         .map(|lib| {
             Ok(DownloadItem {
                 url: lib.get_download_url(),
+                mirrors: vec![],
                 path: lib.get_full_path(),
                 hash: None,
-                extract: false,
+                size: None,
+                extract: None,
             })
         })
         .collect()
 }
 
+/// Adds a task installing the Fabric loader, to run after `deps`.
+///
+/// Nothing is installed if `fabric_version` turns out to be `None`.
+pub fn add_tasks(
+    graph: &mut TaskGraph,
+    minecraft_version: Slot<String>,
+    fabric_version: Slot<Option<String>>,
+    deps: &[TaskId],
+) -> TaskId {
+    graph.add("Install Fabric", deps, move |task| {
+        let Some(fabric_version) = fabric_version.get()?.as_ref().clone() else {
+            return Ok(());
+        };
+
+        let items = get_download_items(task.ctx(), &minecraft_version.get()?, &fabric_version)?;
+        task.download(items)
+    })
+}
+
 /*
 pub fn install(instance: &mut Instance, fabric_version: &str) -> Result<Vec<DownloadItem>> {
     let minecraft_version = &instance.info.minecraft;
@@ -74,3 +146,41 @@ pub fn install(instance: &mut Instance, fabric_version: &str) -> Result<Vec<Down
     Ok(downloads)
 }
 */
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::cache::MetaCache;
+    use crate::endpoints::Endpoints;
+    use crate::transport::MemoryTransport;
+
+    #[test]
+    fn profile_is_downloaded_and_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let transport = Arc::new(MemoryTransport::new());
+        transport.add_json(
+            "https://meta.fabricmc.net/v2/versions/loader/1.20.4/0.15.3/profile/json",
+            &json!({
+                "mainClass": "net.fabricmc.loader.impl.launch.knot.KnotClient",
+                "libraries": [
+                    { "name": "net.fabricmc:fabric-loader:0.15.3", "url": "https://maven.fabricmc.net/" },
+                ],
+            }),
+        );
+        let ctx = Context::with_transport(transport, Endpoints::default())
+            .with_cache(MetaCache::new(dir.path().join("cache")));
+
+        let path = dir.path().join("fabric-loader-0.15.3-1.20.4.json");
+        let meta = fetch_meta(&ctx, "1.20.4", "0.15.3", path.clone()).unwrap();
+
+        assert_eq!(
+            meta.libraries[0].get_download_url(),
+            "https://maven.fabricmc.net/net/fabricmc/fabric-loader/0.15.3/fabric-loader-0.15.3.jar"
+        );
+        assert!(path.exists());
+    }
+}
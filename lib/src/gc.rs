@@ -0,0 +1,198 @@
+// SPDX-FileCopyrightText: 2023 Manuel Quarneti <manuelquarneti@protonmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    collections::HashSet,
+    ffi::OsStr,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Result};
+
+use crate::instances::Instance;
+use crate::paths::{ASSETS_DIR, LIBRARIES_DIR, RUNTIMES_DIR, STORE_DIR};
+use crate::runtimes::RuntimeOverview;
+use crate::{fabric, vanilla_installer};
+
+/// Shared files no instance uses anymore.
+#[derive(Debug, Clone, Default)]
+pub struct GarbageReport {
+    /// Files and runtime directories, already gone unless this was a dry run
+    pub paths: Vec<PathBuf>,
+    /// Bytes taken by `paths`
+    pub size: u64,
+}
+
+/// Walks `dir` and returns every file in it, leaving partial downloads alone.
+fn list_files(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    if !dir.exists() {
+        return Ok(());
+    }
+
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+
+        if entry.file_type()?.is_dir() {
+            list_files(&path, files)?;
+        } else if path.extension() != Some(OsStr::new("part")) {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+fn get_size(path: &Path) -> io::Result<u64> {
+    let metadata = fs::symlink_metadata(path)?;
+
+    if !metadata.is_dir() {
+        return Ok(metadata.len());
+    }
+
+    let mut size = 0;
+    for entry in fs::read_dir(path)? {
+        size += get_size(&entry?.path())?;
+    }
+
+    Ok(size)
+}
+
+/// Removes the directories left empty under `dir`, but not `dir` itself.
+fn remove_empty_dirs(dir: &Path) -> io::Result<()> {
+    if !dir.exists() {
+        return Ok(());
+    }
+
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+
+        if entry.file_type()?.is_dir() {
+            let path = entry.path();
+            remove_empty_dirs(&path)?;
+
+            if fs::read_dir(&path)?.next().is_none() {
+                fs::remove_dir(&path)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether no instance links to the stored file anymore.
+#[cfg(unix)]
+pub(crate) fn is_unused_in_store(path: &Path) -> io::Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+
+    Ok(fs::metadata(path)?.nlink() == 1)
+}
+
+/// Links can't be counted here, so stored files are kept.
+#[cfg(not(unix))]
+pub(crate) fn is_unused_in_store(_path: &Path) -> io::Result<bool> {
+    Ok(false)
+}
+
+/// The libraries, assets and runtime directories `instances` need.
+///
+/// Everything is worked out from the metadata on disk, and it fails if the files of an instance
+/// can't be told, as any file might be in use then.
+pub(crate) fn get_used_files(instances: &[(String, Instance)]) -> Result<HashSet<PathBuf>> {
+    let mut used = HashSet::new();
+
+    for (name, instance) in instances {
+        let mut files = vanilla_installer::get_installed_files(&instance.minecraft);
+
+        if let Some(fabric_version) = &instance.fabric {
+            files = files.and_then(|mut files| {
+                files.extend(fabric::get_installed_files(
+                    &instance.minecraft,
+                    fabric_version,
+                )?);
+                Ok(files)
+            });
+        }
+
+        let files =
+            files.map_err(|error| anyhow!("Can't tell which files {} uses: {}", name, error))?;
+        used.extend(files);
+    }
+
+    let runtimes = RuntimeOverview::load(instances)?;
+    used.extend(
+        runtimes
+            .instances
+            .into_iter()
+            .filter_map(|instance| instance.dir),
+    );
+
+    Ok(used)
+}
+
+/// Finds the libraries, assets, runtimes and stored files none of `instances` need.
+///
+/// If the files of an instance can't be told, nothing is removed. Unless `dry_run` is set, the
+/// files found are deleted.
+pub fn collect(instances: &[(String, Instance)], dry_run: bool) -> Result<GarbageReport> {
+    let used = get_used_files(instances)?;
+    let runtimes = RuntimeOverview::load(instances)?;
+
+    let mut files = Vec::new();
+    list_files(&LIBRARIES_DIR, &mut files)?;
+    list_files(&ASSETS_DIR.join("indexes"), &mut files)?;
+    list_files(&ASSETS_DIR.join("objects"), &mut files)?;
+
+    let mut paths = files
+        .into_iter()
+        .filter(|path| !used.contains(path))
+        .collect::<Vec<_>>();
+
+    let mut stored = Vec::new();
+    list_files(&STORE_DIR, &mut stored)?;
+    for path in stored {
+        if is_unused_in_store(&path)? {
+            paths.push(path);
+        }
+    }
+
+    for runtime in runtimes.get_unused() {
+        paths.push(runtime.dir.clone());
+    }
+
+    let mut report = GarbageReport::default();
+    for path in paths {
+        report.size += get_size(&path)?;
+        report.paths.push(path);
+    }
+
+    if dry_run {
+        return Ok(report);
+    }
+
+    for path in &report.paths {
+        println!("removing unused file: {}", path.display());
+
+        if fs::symlink_metadata(path)?.is_dir() {
+            fs::remove_dir_all(path)?;
+        } else {
+            fs::remove_file(path)?;
+        }
+    }
+
+    remove_empty_dirs(&LIBRARIES_DIR)?;
+    remove_empty_dirs(&ASSETS_DIR.join("objects"))?;
+    remove_empty_dirs(&STORE_DIR)?;
+
+    // only the major version directories, runtimes can have empty directories of their own
+    for entry in fs::read_dir(&*RUNTIMES_DIR)? {
+        let path = entry?.path();
+
+        if path.is_dir() && fs::read_dir(&path)?.next().is_none() {
+            fs::remove_dir(&path)?;
+        }
+    }
+
+    Ok(report)
+}
@@ -2,7 +2,8 @@
 // SPDX-License-Identifier: GPL-3.0-only
 
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::future::Future;
+use std::path::{Path, PathBuf};
 use std::{fs, process};
 
 use anyhow::{anyhow, Result};
@@ -10,8 +11,11 @@ use serde::{Deserialize, Serialize};
 use time::OffsetDateTime;
 
 use crate::accounts::Account;
-use crate::paths::{ASSETS_DIR, BASE_DIR};
-use crate::{adoptium, vanilla_installer};
+use crate::gc::GarbageReport;
+use crate::paths::BASE_DIR;
+use crate::runtimes::{Runtime, RuntimeOverview};
+use crate::vanilla_installer::RepairReport;
+use crate::{blocking, gc, modrinth, runtimes, vanilla_installer, Context, DownloadQueue};
 
 // https://github.com/brucethemoose/Minecraft-Performance-Flags-Benchmarks
 const OPTIMIZED_FLAGS: &str = " -XX:+UnlockExperimentalVMOptions -XX:+UnlockDiagnosticVMOptions -XX:+AlwaysActAsServerClassMachine -XX:+AlwaysPreTouch -XX:+DisableExplicitGC -XX:+UseNUMA -XX:NmethodSweepActivity=1 -XX:ReservedCodeCacheSize=400M -XX:NonNMethodCodeHeapSize=12M -XX:ProfiledCodeHeapSize=194M -XX:NonProfiledCodeHeapSize=194M -XX:-DontCompileHugeMethods -XX:MaxNodeLimit=240000 -XX:NodeLimitFudgeFactor=8000 -XX:+UseVectorCmov -XX:+PerfDisableSharedMem -XX:+UseFastUnorderedTimeStamps -XX:+UseCriticalJavaThreadPriority -XX:ThreadPriorityPolicy=1 -XX:AllocatePrefetchStyle=3 -XX:+UseShenandoahGC -XX:ShenandoahGCMode=iu -XX:ShenandoahGuaranteedGCInterval=1000000 -XX:AllocatePrefetchStyle=1";
@@ -23,6 +27,22 @@ pub struct Instance {
     pub fabric: Option<String>,
     pub optimize_jvm: bool,
     pub memory: String,
+    /// Java major version to run with instead of the one the version asks for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub java: Option<u32>,
+    /// Java release to run with instead of the newest installed one, like `jdk-17.0.9+9`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub java_release: Option<String>,
+}
+
+impl Instance {
+    /// The Java major version this instance runs on.
+    pub fn get_java_major(&self) -> Result<u32> {
+        match self.java {
+            Some(java) => Ok(java),
+            None => Ok(vanilla_installer::VersionMeta::load(&self.minecraft)?.get_java_major()),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -74,6 +94,91 @@ impl Instances {
         Ok(())
     }
 
+    /// Rehashes the files of an instance on the blocking pool, see
+    /// [`vanilla_installer::repair_version`]. The mods of a modpack are checked too.
+    pub fn repair(
+        &self,
+        ctx: &Context,
+        name: &str,
+    ) -> impl Future<Output = Result<(DownloadQueue, RepairReport)>> + 'static {
+        let instance = self.list.get(name).cloned();
+        let dir = self.get_dir(name);
+        let ctx = ctx.clone();
+
+        async move {
+            let instance = instance.ok_or_else(|| anyhow!("Instance not found"))?;
+
+            blocking::run(move || {
+                let mut items = vanilla_installer::get_version_items(
+                    &ctx,
+                    &instance.minecraft,
+                    instance.fabric.as_deref(),
+                    instance.java,
+                    instance.java_release.as_deref(),
+                )?;
+                items.extend(modrinth::get_installed_items(&dir)?);
+
+                vanilla_installer::repair_items(items)
+            })
+            .await
+        }
+    }
+
+    /// Looks for shared files no instance uses anymore on the blocking pool, deleting them
+    /// unless `dry_run` is set. See [`gc::collect`].
+    pub fn collect_garbage(
+        &self,
+        dry_run: bool,
+    ) -> impl Future<Output = Result<GarbageReport>> + 'static {
+        let instances = self.get_list();
+
+        blocking::run(move || gc::collect(&instances, dry_run))
+    }
+
+    pub(crate) fn get_list(&self) -> Vec<(String, Instance)> {
+        self.list
+            .iter()
+            .map(|(name, instance)| (name.clone(), instance.clone()))
+            .collect()
+    }
+
+    /// Lists the installed Java runtimes and what each instance runs on, on the blocking pool.
+    pub fn get_runtimes(&self) -> impl Future<Output = Result<RuntimeOverview>> + 'static {
+        let instances = self.get_list();
+
+        blocking::run(move || RuntimeOverview::load(&instances))
+    }
+
+    /// Deletes the Java runtimes no instance launches with, on the blocking pool.
+    pub fn remove_unused_runtimes(&self) -> impl Future<Output = Result<Vec<Runtime>>> + 'static {
+        let instances = self.get_list();
+
+        blocking::run(move || {
+            let overview = RuntimeOverview::load(&instances)?;
+            let unused = overview.get_unused();
+
+            for runtime in &unused {
+                runtime.delete()?;
+            }
+
+            Ok(unused.into_iter().cloned().collect())
+        })
+    }
+
+    /// Pins an instance to a Java release, or lets it follow the newest one again.
+    pub fn pin_runtime(&mut self, name: &str, release: Option<String>) -> Result<()> {
+        let path = self.get_config_path(name);
+        let instance = self
+            .list
+            .get_mut(name)
+            .ok_or_else(|| anyhow!("Instance not found"))?;
+
+        instance.java_release = release;
+        fs::write(path, toml::to_string_pretty(instance)?)?;
+
+        Ok(())
+    }
+
     pub fn get_config_path(&self, name: &str) -> PathBuf {
         self.get_dir(name).join("instance.toml")
     }
@@ -97,6 +202,8 @@ impl Instances {
             fabric: fabric_version,
             optimize_jvm,
             memory,
+            java: None,
+            java_release: None,
         };
         let info_str = toml::to_string_pretty(&info)?;
         fs::write(self.get_config_path(&name), info_str)?;
@@ -106,19 +213,45 @@ impl Instances {
         Ok(())
     }
 
-    pub fn launch(&self, name: &str, account: &Account) -> Result<()> {
-        let instance = self
-            .list
-            .get(name)
-            .ok_or_else(|| anyhow!("Instance not found"))?;
+    /// Launches an instance, resolving once the game exits.
+    pub fn launch(
+        &self,
+        ctx: &Context,
+        name: &str,
+        account: &Account,
+    ) -> impl Future<Output = Result<()>> + 'static {
+        let instance = self.list.get(name).cloned();
+        let dir = self.get_dir(name);
+        let name = name.to_string();
+        let ctx = ctx.clone();
+        let account = account.clone();
+
+        async move {
+            let instance = instance.ok_or_else(|| anyhow!("Instance not found"))?;
+
+            blocking::run(move || Self::run_game(&ctx, &name, &dir, &instance, &account)).await
+        }
+    }
 
+    fn run_game(
+        ctx: &Context,
+        name: &str,
+        dir: &Path,
+        instance: &Instance,
+        account: &Account,
+    ) -> Result<()> {
         let version_meta = vanilla_installer::VersionMeta::load(&instance.minecraft)?;
 
-        let java_path = adoptium::get_path("17")?;
+        let java_major = instance
+            .java
+            .unwrap_or_else(|| version_meta.get_java_major());
+        let runtime = runtimes::find(java_major, instance.java_release.as_deref())
+            .map_err(|error| anyhow!("{}, repair the instance to install it", error))?;
 
         let mut jvm_flags = format!("-Xmx{0} -Xms{0}", instance.memory);
 
-        if instance.optimize_jvm {
+        // the flags rely on Shenandoah, which Java 8 builds don't have
+        if instance.optimize_jvm && java_major >= 17 {
             jvm_flags.push_str(OPTIMIZED_FLAGS);
 
             if cfg!(target_os = "linux") {
@@ -126,52 +259,33 @@ impl Instances {
             }
         }
 
-        if cfg!(target_os = "macos") {
-            jvm_flags.push_str(" -XstartOnFirstThread");
-        }
-
-        let mut child = process::Command::new(java_path)
-            .current_dir(&self.get_dir(name))
+        let proxy_flags = ctx
+            .proxy()
+            .map(|proxy| proxy.jvm_args())
+            .unwrap_or_default();
+
+        // removed when the game exits
+        let natives = version_meta.extract_natives()?;
+        let launch = version_meta.get_launch_context(
+            account,
+            dir.to_path_buf(),
+            natives.path().to_path_buf(),
+        )?;
+
+        let mut child = process::Command::new(runtime.get_java_path())
+            .current_dir(dir)
             .args(jvm_flags.split(' '))
-            .arg("-cp")
-            .arg(version_meta.get_classpath()?)
-            .arg(format!(
-                "-Dminecraft.launcher.brand={}",
-                env!("CARGO_PKG_NAME")
-            ))
-            .arg(format!(
-                "-Dminecraft.launcher.version={}",
-                env!("CARGO_PKG_VERSION")
-            ))
-            .arg(version_meta.main_class)
-            .arg("--username")
-            .arg(&account.mc_username)
-            .arg("--uuid")
-            .arg(&account.mc_id)
-            .arg("--accessToken")
-            .arg(&account.mc_access_token)
-            .arg("--userType")
-            .arg("msa")
-            .arg("--version")
-            .arg(&instance.minecraft)
-            .arg("--gameDir")
-            .arg(".")
-            .arg("--assetsDir")
-            .arg(ASSETS_DIR.to_string_lossy().to_string())
-            .arg("--assetIndex")
-            .arg(version_meta.assets)
-            .arg("--versionType")
-            .arg("release")
-            .arg("--clientId")
-            .arg(format!(
-                "{}/{}",
-                env!("CARGO_PKG_NAME"),
-                env!("CARGO_PKG_VERSION")
-            ))
+            .args(proxy_flags)
+            .args(version_meta.get_jvm_arguments(&launch))
+            .arg(&version_meta.main_class)
+            .args(version_meta.get_game_arguments(&launch))
             .spawn()?;
 
         println!("Launched instance: {}", name);
 
+        // keep downloads out of the way until the game exits
+        let _game = ctx.bandwidth().game_started();
+
         child.wait()?;
         Ok(())
     }
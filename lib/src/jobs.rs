@@ -0,0 +1,477 @@
+// SPDX-FileCopyrightText: 2023 Manuel Quarneti <manuelquarneti@protonmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use tempfile::NamedTempFile;
+
+use crate::instances::Instances;
+use crate::paths::{JOBS_DIR, STORE_DIR};
+use crate::tasks::TaskGraph;
+use crate::{gc, modrinth, runtimes, vanilla_installer, DownloadItem, DownloadQueue};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ItemStatus {
+    Pending,
+    /// The file was already there before the job started
+    Skipped,
+    Done,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobItem {
+    pub item: DownloadItem,
+    pub status: ItemStatus,
+}
+
+/// What a job installs, so a resumed job can run its install again from the start.
+///
+/// Installs made of tasks only learn their files as they go, so their items alone can't tell
+/// what's left to do.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum JobPlan {
+    /// A list of files known upfront, like a repair
+    #[default]
+    Files,
+    /// A Minecraft version, with the Fabric loader and the Java override of the instance
+    Version {
+        id: String,
+        fabric: Option<String>,
+        #[serde(default)]
+        java_major: Option<u32>,
+        #[serde(default)]
+        java_release: Option<String>,
+    },
+    /// The newest release of a Java major version
+    JavaUpdate { major: u32 },
+    /// A Modrinth modpack file, installed into `dest_dir`
+    Modpack {
+        url: String,
+        sha512: String,
+        size: u64,
+        dest_dir: PathBuf,
+    },
+    /// A graph that can't be built again, so it can only be rolled back
+    Graph,
+}
+
+impl JobPlan {
+    /// A new graph running the install again, unless this is a plain list of files.
+    pub fn build(&self) -> Option<TaskGraph> {
+        match self {
+            JobPlan::Files | JobPlan::Graph => None,
+            JobPlan::Version {
+                id,
+                fabric,
+                java_major,
+                java_release,
+            } => Some(vanilla_installer::download_version(
+                id,
+                fabric.as_deref(),
+                *java_major,
+                java_release.as_deref(),
+            )),
+            JobPlan::JavaUpdate { major } => Some(runtimes::download_update(*major)),
+            JobPlan::Modpack {
+                url,
+                sha512,
+                size,
+                dest_dir,
+            } => Some(modrinth::install_pack(url, sha512, *size, dest_dir)),
+        }
+    }
+}
+
+/// A download queue saved under `BASE_DIR`, so an interrupted install can be picked up again.
+///
+/// The record is kept up to date while the queue runs and removed once every item is in place.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstallJob {
+    pub id: String,
+    pub description: String,
+    /// The instance this job is installing, removed on rollback
+    pub instance: Option<String>,
+    #[serde(default)]
+    pub plan: JobPlan,
+    pub items: Vec<JobItem>,
+}
+
+impl InstallJob {
+    pub fn new(
+        description: &str,
+        instance: Option<&str>,
+        plan: JobPlan,
+        items: &[DownloadItem],
+    ) -> Self {
+        let id = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis()
+            .to_string();
+
+        let mut job = Self {
+            id,
+            description: description.to_string(),
+            instance: instance.map(str::to_string),
+            plan,
+            items: Vec::new(),
+        };
+        job.add_items(items);
+
+        job
+    }
+
+    /// Returns every job left over from a previous run.
+    pub fn load_all() -> Result<Vec<Self>> {
+        let mut jobs = Vec::new();
+
+        for entry in fs::read_dir(&*JOBS_DIR)? {
+            let path = entry?.path();
+
+            if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
+                continue;
+            }
+
+            match fs::read(&path).map(|bytes| serde_json::from_slice::<Self>(&bytes)) {
+                Ok(Ok(job)) => jobs.push(job),
+                Ok(Err(error)) => println!("Invalid job {}: {}", path.display(), error),
+                Err(error) => println!("Failed to read job {}: {}", path.display(), error),
+            }
+        }
+
+        jobs.sort_by(|a, b| a.id.cmp(&b.id));
+
+        Ok(jobs)
+    }
+
+    fn path(&self) -> PathBuf {
+        JOBS_DIR.join(format!("{}.json", self.id))
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let mut file = NamedTempFile::new_in(&*JOBS_DIR)?;
+        serde_json::to_writer(&mut file, self)?;
+        file.persist(self.path())?;
+
+        Ok(())
+    }
+
+    /// Deletes the record, leaving the files alone.
+    pub fn remove(&self) -> Result<()> {
+        match fs::remove_file(self.path()) {
+            Err(error) if error.kind() != io::ErrorKind::NotFound => Err(error.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Adds more items, returning the index of each.
+    ///
+    /// Items the job already has keep their entry, so a resumed job still owns the files it
+    /// downloaded before.
+    pub fn add_items(&mut self, items: &[DownloadItem]) -> Vec<usize> {
+        let mut known = self
+            .items
+            .iter()
+            .enumerate()
+            .map(|(index, item)| (item.item.path.clone(), index))
+            .collect::<HashMap<_, _>>();
+
+        items
+            .iter()
+            .map(|item| {
+                *known.entry(item.path.clone()).or_insert_with(|| {
+                    self.items.push(JobItem {
+                        item: item.clone(),
+                        status: ItemStatus::Pending,
+                    });
+                    self.items.len() - 1
+                })
+            })
+            .collect()
+    }
+
+    pub fn set_status(&mut self, index: usize, status: ItemStatus) {
+        if let Some(item) = self.items.get_mut(index) {
+            // a resumed job skips what it downloaded last time, but it still owns those files
+            if !(item.status == ItemStatus::Done && status == ItemStatus::Skipped) {
+                item.status = status;
+            }
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.items
+            .iter()
+            .all(|item| matches!(item.status, ItemStatus::Done | ItemStatus::Skipped))
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.items
+            .iter()
+            .filter(|item| matches!(item.status, ItemStatus::Pending | ItemStatus::Failed))
+            .count()
+    }
+
+    /// Whether the job can be picked up again, otherwise it can only be rolled back.
+    pub fn can_resume(&self) -> bool {
+        self.plan != JobPlan::Graph
+    }
+
+    /// Turns the job back into a graph. Files already in place are skipped when it runs.
+    pub fn resume(self) -> Result<TaskGraph> {
+        if let Some(graph) = self.plan.build() {
+            return Ok(graph.attach_job(self));
+        }
+
+        if !self.can_resume() {
+            bail!(
+                "{} can't be resumed, roll it back instead",
+                self.description
+            );
+        }
+
+        let description = self.description.clone();
+        let items = self.items.iter().map(|item| item.item.clone()).collect();
+        let queue = DownloadQueue::new(items).attach_job(JobHandle::owned(self));
+
+        Ok(TaskGraph::from_queue(&description, queue))
+    }
+
+    /// Deletes everything this job downloaded, the instance it was creating, and the record.
+    ///
+    /// Files that were already there before the job started are left alone, and so are those
+    /// the other instances have started using since.
+    pub fn rollback(self, instances: &mut Instances) -> Result<()> {
+        let others = instances
+            .get_list()
+            .into_iter()
+            .filter(|(name, _)| Some(name) != self.instance.as_ref())
+            .collect::<Vec<_>>();
+
+        match gc::get_used_files(&others) {
+            Ok(used) => self.remove_files(|path| {
+                used.contains(path)
+                    || (path.starts_with(&*STORE_DIR)
+                        && !gc::is_unused_in_store(path).unwrap_or(false))
+            }),
+            // any file might be in use, so only the instance goes
+            Err(error) => println!("Keeping the files of {}: {}", self.description, error),
+        }
+
+        if let Some(instance) = &self.instance {
+            if instances.get_dir(instance).exists() {
+                instances.delete(instance)?;
+            }
+        }
+
+        self.remove()
+    }
+
+    /// Deletes the files this job downloaded, unless they're `in_use`.
+    fn remove_files(&self, in_use: impl Fn(&Path) -> bool) {
+        for JobItem { item, status } in &self.items {
+            let _ = fs::remove_file(item.part_path());
+
+            if *status == ItemStatus::Done && !in_use(&item.path) {
+                let result = if item.extract.is_some() {
+                    fs::remove_dir_all(&item.path)
+                } else {
+                    fs::remove_file(&item.path)
+                };
+
+                if let Err(error) = result {
+                    if error.kind() != io::ErrorKind::NotFound {
+                        println!("Failed to remove {}: {}", item.path.display(), error);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The part of a job a single queue reports to.
+///
+/// Several queues can share a job, each owning some of its items.
+#[derive(Debug, Clone)]
+pub(crate) struct JobHandle {
+    job: Arc<Mutex<InstallJob>>,
+    /// The index in the job of each item of the queue
+    indices: Vec<usize>,
+    /// No other queue uses the job, so it can be removed once the queue is done
+    owned: bool,
+}
+
+impl JobHandle {
+    pub(crate) fn owned(job: InstallJob) -> Self {
+        Self {
+            indices: (0..job.items.len()).collect(),
+            job: Arc::new(Mutex::new(job)),
+            owned: true,
+        }
+    }
+
+    /// Adds `items` to a shared job and saves it.
+    pub(crate) fn shared(job: &Arc<Mutex<InstallJob>>, items: &[DownloadItem]) -> Result<Self> {
+        let indices = {
+            let mut job = job.lock().unwrap();
+            let indices = job.add_items(items);
+            job.save()?;
+            indices
+        };
+
+        Ok(Self {
+            job: Arc::clone(job),
+            indices,
+            owned: false,
+        })
+    }
+
+    pub(crate) fn set_status(&self, index: usize, status: ItemStatus) {
+        if let Some(index) = self.indices.get(index) {
+            self.job.lock().unwrap().set_status(*index, status);
+        }
+    }
+
+    pub(crate) fn save(&self) {
+        let job = self.job.lock().unwrap();
+
+        if let Err(error) = job.save() {
+            println!("Failed to save job {}: {}", job.id, error);
+        }
+    }
+
+    /// Forgets the job once everything is in place, or saves it for next time.
+    ///
+    /// Shared jobs are only saved, whoever shares them decides when they're done.
+    pub(crate) fn finish(&self) {
+        let job = self.job.lock().unwrap();
+
+        if self.owned && job.is_complete() {
+            if let Err(error) = job.remove() {
+                println!("Failed to remove job {}: {}", job.id, error);
+            }
+        } else if let Err(error) = job.save() {
+            println!("Failed to save job {}: {}", job.id, error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashSet;
+
+    use crate::ExtractOptions;
+
+    fn item(path: &str) -> DownloadItem {
+        DownloadItem {
+            url: format!("https://example.com/{}", path),
+            mirrors: vec![],
+            path: PathBuf::from(path),
+            hash: None,
+            size: None,
+            extract: None,
+        }
+    }
+
+    #[test]
+    fn add_items_reuses_known_paths() {
+        let mut job = InstallJob::new("Test", None, JobPlan::Graph, &[item("a"), item("b")]);
+        job.set_status(0, ItemStatus::Done);
+
+        let indices = job.add_items(&[item("c"), item("a"), item("c")]);
+
+        assert_eq!(indices, vec![2, 0, 2]);
+        assert_eq!(job.items.len(), 3);
+        assert_eq!(job.items[0].status, ItemStatus::Done);
+    }
+
+    #[test]
+    fn skipped_keeps_done() {
+        let mut job = InstallJob::new("Test", None, JobPlan::Files, &[item("a")]);
+        job.set_status(0, ItemStatus::Done);
+        job.set_status(0, ItemStatus::Skipped);
+
+        assert_eq!(job.items[0].status, ItemStatus::Done);
+        assert!(job.is_complete());
+    }
+
+    #[test]
+    fn rollback_keeps_files_other_instances_use() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name);
+
+        fs::write(path("shared.jar"), "").unwrap();
+        fs::write(path("own.jar"), "").unwrap();
+        fs::create_dir_all(path("jdk-17.0.9+9-jre/bin")).unwrap();
+        fs::write(path("jdk-17.0.9+9-jre/bin/java"), "").unwrap();
+
+        let mut runtime = item("");
+        runtime.path = path("jdk-17.0.9+9-jre");
+        runtime.extract = Some(ExtractOptions::default());
+
+        let items = [
+            DownloadItem {
+                path: path("shared.jar"),
+                ..item("")
+            },
+            DownloadItem {
+                path: path("own.jar"),
+                ..item("")
+            },
+            runtime,
+        ];
+        let mut job = InstallJob::new("Test", None, JobPlan::Graph, &items);
+        for index in 0..items.len() {
+            job.set_status(index, ItemStatus::Done);
+        }
+
+        // another instance installed since, finding these already there
+        let used = HashSet::from([path("shared.jar"), path("jdk-17.0.9+9-jre")]);
+        job.remove_files(|path| used.contains(path));
+
+        assert!(path("shared.jar").exists());
+        assert!(path("jdk-17.0.9+9-jre/bin/java").exists());
+        assert!(!path("own.jar").exists());
+    }
+
+    #[test]
+    fn plan_round_trips() {
+        let plan = JobPlan::Version {
+            id: "1.20.4".to_string(),
+            fabric: Some("0.15.7".to_string()),
+            java_major: Some(21),
+            java_release: None,
+        };
+        let json = serde_json::to_string(&plan).unwrap();
+
+        assert_eq!(serde_json::from_str::<JobPlan>(&json).unwrap(), plan);
+        assert!(!InstallJob::new("Test", None, JobPlan::Graph, &[]).can_resume());
+
+        let plan = modrinth::install_pack(
+            "https://cdn.modrinth.com/fo.mrpack",
+            "abc",
+            42,
+            Path::new("instances/fo"),
+        )
+        .plan()
+        .clone();
+        let json = serde_json::to_string(&plan).unwrap();
+
+        assert!(matches!(plan, JobPlan::Modpack { size: 42, .. }));
+        assert_eq!(serde_json::from_str::<JobPlan>(&json).unwrap(), plan);
+        assert!(InstallJob::new("Test", None, plan, &[]).can_resume());
+    }
+}
@@ -3,42 +3,72 @@
 
 pub mod accounts;
 pub mod adoptium;
+pub mod blocking;
+pub mod cache;
+pub mod context;
+pub mod control;
+pub mod endpoints;
+pub mod extract;
 pub mod fabric;
+pub mod gc;
 pub mod instances;
+pub mod jobs;
 pub mod modrinth;
 pub mod paths;
+pub mod proxy;
+pub mod runtimes;
 pub mod settings;
+pub mod space;
+pub mod store;
+pub mod tasks;
+pub mod throttle;
+pub mod transport;
 pub mod updater;
 pub mod vanilla_installer;
+pub mod verify;
 
 use std::{
-    fs::{self, File},
-    io::{self, BufReader, BufWriter, Read, Seek},
-    path::PathBuf,
+    collections::{hash_map::RandomState, HashMap, VecDeque},
+    fs::{self, File, OpenOptions},
+    hash::{BuildHasher, Hasher as _},
+    io::{self, BufReader, BufWriter, Read, Seek, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc, Condvar, Mutex,
+    },
+    thread,
+    time::Duration,
 };
 
-use anyhow::{anyhow, bail, Result};
-use digest::Digest;
-use flate2::bufread::GzDecoder;
-use once_cell::sync::Lazy;
-use sha1::Sha1;
-use sha2::{Sha256, Sha512};
-use tar::Archive;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
 use tempfile::NamedTempFile;
-use ureq::{Agent, AgentBuilder};
-use zip::ZipArchive;
 
-const USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
-pub static AGENT: Lazy<Agent> = Lazy::new(|| AgentBuilder::new().user_agent(USER_AGENT).build());
-
-#[derive(Debug, Clone, PartialEq, Eq)]
+pub use crate::context::Context;
+pub use crate::control::DownloadControl;
+pub use crate::extract::ExtractOptions;
+use crate::jobs::{InstallJob, ItemStatus, JobHandle, JobPlan};
+use crate::transport::{HttpError, Request};
+pub use crate::verify::DownloadError;
+use crate::verify::Hasher;
+
+pub const DEFAULT_MAX_CONCURRENT_DOWNLOADS: usize = 16;
+pub const DEFAULT_MAX_DOWNLOADS_PER_HOST: usize = 8;
+pub const DEFAULT_MAX_DOWNLOAD_ATTEMPTS: u32 = 5;
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(500);
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum HashAlgorithm {
     Sha1,
     Sha256,
     Sha512,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Hash {
     pub hash: String,
     pub function: HashAlgorithm,
@@ -54,18 +84,130 @@ impl Hash {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct DownloadItem {
     pub url: String,
+    pub mirrors: Vec<String>,
     pub path: PathBuf,
     pub hash: Option<Hash>,
-    pub extract: bool,
+    pub size: Option<u64>,
+    /// Extract the archive into `path` instead of saving it there
+    pub extract: Option<ExtractOptions>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ItemState {
+    Valid,
+    Missing,
+    Corrupted,
+}
+
+#[derive(Default)]
+struct ByteCounter {
+    transferred: AtomicU64,
+    skipped: AtomicU64,
+}
+
+impl ByteCounter {
+    /// Takes back the bytes of a partial file counted as skipped, once it's downloaded again.
+    fn take_back(&self, credited: &mut u64) {
+        self.skipped
+            .fetch_sub(std::mem::take(credited), Ordering::Relaxed);
+    }
+}
+
+struct CountingReader<'a, R> {
+    inner: R,
+    counter: &'a AtomicU64,
+}
+
+impl<R: Read> Read for CountingReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = self.inner.read(buf)?;
+        self.counter.fetch_add(count as u64, Ordering::Relaxed);
+
+        Ok(count)
+    }
 }
 
 impl DownloadItem {
-    pub fn download_file(&self) -> Result<()> {
+    pub fn download_file(&self, ctx: &Context) -> Result<(), DownloadError> {
+        self.download_file_counting(
+            ctx,
+            &ByteCounter::default(),
+            &mut 0,
+            &DownloadControl::default(),
+        )
+    }
+
+    /// Returns the URLs to download from, primary first, followed by the fallback mirrors.
+    pub fn urls(&self, ctx: &Context) -> Vec<String> {
+        let endpoints = ctx.endpoints();
+        let mut urls = endpoints.resolve(&self.url);
+
+        for mirror in &self.mirrors {
+            for url in endpoints.resolve(mirror) {
+                if !urls.contains(&url) {
+                    urls.push(url);
+                }
+            }
+        }
+
+        urls
+    }
+
+    /// Where the file is downloaded to before it is verified and moved into place.
+    ///
+    /// Partial downloads are kept here, so an interrupted download can be resumed later.
+    pub fn part_path(&self) -> PathBuf {
+        let mut file_name = self.path.file_name().unwrap_or_default().to_os_string();
+        file_name.push(".part");
+
+        self.path.with_file_name(file_name)
+    }
+
+    /// Checks the file on disk against the expected size and hash.
+    ///
+    /// Extracted archives can't be rehashed, so they only need to exist.
+    pub fn check(&self) -> io::Result<ItemState> {
+        if !self.path.exists() {
+            return Ok(ItemState::Missing);
+        }
+
+        if self.extract.is_some() {
+            return Ok(ItemState::Valid);
+        }
+
+        if let Some(size) = self.size {
+            if fs::metadata(&self.path)?.len() != size {
+                return Ok(ItemState::Corrupted);
+            }
+        }
+
+        if let Some(hash) = &self.hash {
+            let actual = verify::hash_file(&self.path, &hash.function)?;
+
+            if !actual.eq_ignore_ascii_case(&hash.hash) {
+                return Ok(ItemState::Corrupted);
+            }
+        }
+
+        Ok(ItemState::Valid)
+    }
+
+    /// `credited` is the part of the partial file already counted as skipped.
+    fn download_file_counting(
+        &self,
+        ctx: &Context,
+        counter: &ByteCounter,
+        credited: &mut u64,
+        control: &DownloadControl,
+    ) -> Result<(), DownloadError> {
         if self.path.exists() {
             println!("file already exists: {}", self.path.display());
+            counter
+                .skipped
+                .fetch_add(self.size.unwrap_or(0), Ordering::Relaxed);
             return Ok(());
         }
 
@@ -73,156 +215,741 @@ impl DownloadItem {
 
         // create parent directory
         {
-            let parent = self
-                .path
-                .parent()
-                .ok_or_else(|| anyhow!("invalid path: {}", self.path.display()))?;
+            let parent = self.path.parent().ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("invalid path: {}", self.path.display()),
+                )
+            })?;
             fs::create_dir_all(parent)?;
         }
 
-        let response = AGENT.get(&self.url).call()?;
-        let mut file = NamedTempFile::new()?;
-
-        // write to file
-        {
-            let mut writer = BufWriter::new(&mut file);
-            io::copy(&mut response.into_reader(), &mut writer)?;
-            writer.seek(io::SeekFrom::Start(0))?;
+        // try the primary URL first, then every mirror
+        let part_path = self.part_path();
+        let mut last_error = None;
+
+        for url in self.urls(ctx) {
+            match self.fetch(ctx, &url, &part_path, counter, credited, control) {
+                Ok(()) => {
+                    last_error = None;
+                    break;
+                }
+                Err(DownloadError::Cancelled) => return Err(DownloadError::Cancelled),
+                Err(error) => {
+                    println!("download failed: {}: {}", url, error);
+                    last_error = Some(error);
+                }
+            }
         }
 
-        // check hash
-        if let Some(hash) = &self.hash {
-            let mut reader = BufReader::new(&mut file);
-            check_hash(&mut reader, hash)?;
-            reader.seek(io::SeekFrom::Start(0))?;
+        if let Some(error) = last_error {
+            return Err(error);
         }
 
-        if self.extract {
+        if let Some(options) = self.extract {
             println!("extracting archive: {}", self.path.display());
 
-            let reader = BufReader::new(&file);
+            let result = extract::extract(&part_path, &self.path, options);
+            fs::remove_file(&part_path)?;
+            result?;
+        } else {
+            // move file to destination
+            fs::rename(&part_path, &self.path)?;
+        }
+
+        Ok(())
+    }
+
+    /// Downloads `url` into the partial file and verifies it.
+    fn fetch(
+        &self,
+        ctx: &Context,
+        url: &str,
+        part_path: &Path,
+        counter: &ByteCounter,
+        credited: &mut u64,
+        control: &DownloadControl,
+    ) -> Result<(), DownloadError> {
+        let offset = fs::metadata(part_path).map_or(0, |metadata| metadata.len());
+
+        let mut request = Request::get(url);
+        if offset > 0 {
+            println!("resuming download at byte {}: {}", offset, url);
+            request = request.header("Range", &format!("bytes={offset}-"));
+        }
+
+        let response = match ctx.send_resolved(request) {
+            Err(error @ HttpError::Status { code: 416, .. }) => {
+                // the partial file doesn't match the remote one anymore, start over
+                fs::remove_file(part_path)?;
+                counter.take_back(credited);
+                return Err(error.into());
+            }
+            response => response?,
+        };
+
+        // the range was ignored, so the bytes we had are downloaded again
+        if response.status != 206 {
+            counter.take_back(credited);
+        }
+
+        let mut hasher = self.hash.as_ref().map(|hash| Hasher::new(&hash.function));
+
+        // write to file, appending only if the server honoured the range request
+        {
+            let file = if response.status == 206 {
+                // the bytes we already have are part of the digest too
+                if let Some(hasher) = &mut hasher {
+                    hasher.update_from_reader(BufReader::new(File::open(part_path)?))?;
+                }
 
-            if self.url.ends_with(".zip") || self.url.ends_with(".mrpack") {
-                let mut archive = ZipArchive::new(reader)?;
-                archive.extract(self.path.parent().unwrap())?;
-            } else if self.url.ends_with(".tar.gz") {
-                let mut archive = Archive::new(GzDecoder::new(reader));
-                archive.unpack(self.path.parent().unwrap())?;
+                OpenOptions::new().append(true).open(part_path)?
             } else {
-                fs::remove_file(&self.path)?;
-                bail!("unsupported archive format: {}", self.url);
+                File::create(part_path)?
+            };
+
+            let mut reader = CountingReader {
+                inner: response.into_reader(),
+                counter: &counter.transferred,
+            };
+            let mut writer = BufWriter::new(file);
+            let mut buffer = vec![0; 64 * 1024];
+            let mut count = 0;
+
+            loop {
+                // waits out the rate limit for the previous chunk, and any pause
+                if let Err(error) = ctx.bandwidth().throttle(count, control) {
+                    drop(writer);
+                    fs::remove_file(part_path)?;
+                    return Err(error);
+                }
+
+                count = reader.read(&mut buffer)?;
+                if count == 0 {
+                    break;
+                }
+
+                writer.write_all(&buffer[..count])?;
+                if let Some(hasher) = &mut hasher {
+                    hasher.update(&buffer[..count]);
+                }
             }
 
-            fs::remove_file(&file)?;
-        } else {
-            // move file to destination
-            fs::rename(file, &self.path)?;
+            writer.flush()?;
+        }
+
+        // check hash
+        if let (Some(hash), Some(hasher)) = (&self.hash, hasher) {
+            println!("checking hash: {:?} {}", hash.function, hash.hash);
+
+            if let Err(error) = verify::check_digest(hash, hasher.finalize()) {
+                fs::remove_file(part_path)?;
+                return Err(error);
+            }
         }
 
         Ok(())
     }
 
-    pub fn download_json<T: for<'a> serde::Deserialize<'a>>(&self) -> Result<T> {
-        if self.path.exists() {
-            println!("json already exists: {}", self.path.display());
+    /// Downloads the file, retrying transient failures with exponential backoff.
+    fn download_file_with_retries(
+        &self,
+        ctx: &Context,
+        counter: &ByteCounter,
+        control: &DownloadControl,
+        max_attempts: u32,
+    ) -> Result<(), DownloadError> {
+        // bytes left over from a previous run don't need to be downloaded again
+        let mut credited = fs::metadata(self.part_path()).map_or(0, |metadata| metadata.len());
+        counter.skipped.fetch_add(credited, Ordering::Relaxed);
+
+        let mut attempt = 1;
+
+        loop {
+            match self.download_file_counting(ctx, counter, &mut credited, control) {
+                Ok(()) => return Ok(()),
+                Err(error) if attempt < max_attempts && error.is_transient() => {
+                    let delay = backoff_delay(attempt);
+                    println!(
+                        "download failed (attempt {}/{}), retrying in {:?}: {}: {}",
+                        attempt, max_attempts, delay, self.url, error
+                    );
+
+                    // cancelled while waiting, drop the partial file like a cancelled transfer does
+                    if let Err(error) = control.sleep(delay) {
+                        let part_path = self.part_path();
+                        if part_path.exists() {
+                            fs::remove_file(part_path)?;
+                        }
+
+                        return Err(error);
+                    }
+
+                    attempt += 1;
+                }
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    pub fn download_json<T: for<'a> serde::Deserialize<'a>>(&self, ctx: &Context) -> Result<T> {
+        let parent = self
+            .path
+            .parent()
+            .ok_or_else(|| anyhow!("invalid path: {}", self.path.display()))?;
+
+        // without a hash there's no telling whether an existing file is stale
+        if self.hash.is_none() {
+            let bytes = self
+                .urls(ctx)
+                .iter()
+                .find_map(|url| match ctx.get_cached_resolved(url, cache::SHORT_TTL) {
+                    Ok(bytes) => Some(bytes),
+                    Err(error) => {
+                        println!("download failed: {}: {}", url, error);
+                        None
+                    }
+                })
+                .ok_or_else(|| anyhow!("failed to download {}", self.url))?;
+            let json = serde_json::from_slice(&bytes)?;
 
-            let file = File::open(&self.path)?;
-            let reader = BufReader::new(file);
-            let json = serde_json::from_reader(reader)?;
+            fs::create_dir_all(parent)?;
+            let mut file = NamedTempFile::new_in(parent)?;
+            file.write_all(&bytes)?;
+            file.persist(&self.path)?;
 
             return Ok(json);
         }
 
-        println!("downloading json: {} to {}", self.url, self.path.display());
+        match self.check()? {
+            ItemState::Valid => {
+                println!("json already exists: {}", self.path.display());
 
-        // create parent directory
-        {
-            let parent = self
-                .path
-                .parent()
-                .ok_or_else(|| anyhow!("invalid path: {}", self.path.display()))?;
-            fs::create_dir_all(parent)?;
+                let file = File::open(&self.path)?;
+                let reader = BufReader::new(file);
+                let json = serde_json::from_reader(reader)?;
+
+                return Ok(json);
+            }
+            ItemState::Corrupted => {
+                println!("json is corrupted: {}", self.path.display());
+            }
+            ItemState::Missing => {}
         }
 
-        let response = AGENT.get(&self.url).call()?;
-        let file = NamedTempFile::new()?;
+        println!("downloading json: {} to {}", self.url, self.path.display());
 
-        // write to file
+        fs::create_dir_all(parent)?;
+
+        let response = self
+            .urls(ctx)
+            .iter()
+            .find_map(|url| match ctx.send_resolved(Request::get(url)) {
+                Ok(response) => Some(response),
+                Err(error) => {
+                    println!("download failed: {}: {}", url, error);
+                    None
+                }
+            })
+            .ok_or_else(|| anyhow!("failed to download {}", self.url))?;
+        // next to the destination, so it can be moved there
+        let file = NamedTempFile::new_in(parent)?;
+        let mut hasher = self.hash.as_ref().map(|hash| Hasher::new(&hash.function));
+
+        // write to file, hashing along the way
         {
+            let mut reader = response.into_reader();
             let mut writer = BufWriter::new(&file);
-            io::copy(&mut response.into_reader(), &mut writer)?;
+            let mut buffer = vec![0; 64 * 1024];
+
+            loop {
+                let count = reader.read(&mut buffer)?;
+                if count == 0 {
+                    break;
+                }
+
+                writer.write_all(&buffer[..count])?;
+                if let Some(hasher) = &mut hasher {
+                    hasher.update(&buffer[..count]);
+                }
+            }
+
             writer.seek(io::SeekFrom::Start(0))?;
         }
 
         // check hash
-        if let Some(hash) = &self.hash {
-            let mut reader = BufReader::new(&file);
-            check_hash(&mut reader, hash)?;
-            reader.seek(io::SeekFrom::Start(0))?;
+        if let (Some(hash), Some(hasher)) = (&self.hash, hasher) {
+            verify::check_digest(hash, hasher.finalize())?;
         }
 
         let reader = BufReader::new(&file);
         let json = serde_json::from_reader(reader)?;
 
         // move file to destination
-        fs::rename(file, &self.path)?;
+        file.persist(&self.path)?;
 
         Ok(json)
     }
 }
 
-fn calc_hash<D: Digest>(mut reader: impl Read + Seek) -> Result<String> {
-    let mut hasher = D::new();
+fn backoff_delay(attempt: u32) -> Duration {
+    let delay = RETRY_BASE_DELAY
+        .saturating_mul(1 << (attempt - 1).min(16))
+        .min(RETRY_MAX_DELAY);
 
-    loop {
-        let mut buffer = [0; 1024];
-        let count = reader.read(&mut buffer)?;
-        if count == 0 {
-            break;
+    // add up to 50% of random jitter, so failed workers don't all retry at once
+    let max_jitter = delay.as_millis() as u64 / 2;
+    let jitter = RandomState::new().build_hasher().finish() % (max_jitter + 1);
+
+    delay + Duration::from_millis(jitter)
+}
+
+fn get_host(url: &str) -> &str {
+    let url = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let end = url.find(['/', '?', '#']).unwrap_or(url.len());
+
+    &url[..end]
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DownloadProgress {
+    pub items_completed: usize,
+    pub items_total: usize,
+    pub bytes_downloaded: u64,
+    pub bytes_total: u64,
+    pub bytes_per_second: f64,
+    pub eta: Option<Duration>,
+}
+
+impl DownloadProgress {
+    /// Completion in the range 0..=100, by bytes when sizes are known and by items otherwise.
+    pub fn percentage(&self) -> f32 {
+        if self.bytes_total > 0 {
+            (self.bytes_downloaded as f64 / self.bytes_total as f64 * 100.0) as f32
+        } else if self.items_total > 0 {
+            self.items_completed as f32 / self.items_total as f32 * 100.0
+        } else {
+            100.0
         }
-        hasher.update(&buffer[..count]);
     }
+}
 
-    let digest = hasher.finalize();
-    let digest = format!("{:x?}", digest.as_slice());
-
-    Ok(digest)
+#[derive(Debug)]
+pub enum DownloadEvent {
+    Progress(DownloadProgress),
+    Completed {
+        index: usize,
+        completed: usize,
+        total: usize,
+    },
+    Failed {
+        index: usize,
+        completed: usize,
+        total: usize,
+        url: String,
+        error: DownloadError,
+    },
+    Finished,
+    /// Sent instead of `Finished` when the queue was cancelled
+    Cancelled,
 }
 
-fn check_hash(reader: impl Read + Seek, hash: &Hash) -> Result<()> {
-    println!("checking hash: {:?} {}", hash.function, hash.hash);
+struct Scheduler {
+    pending: VecDeque<(usize, DownloadItem)>,
+    active_per_host: HashMap<String, usize>,
+    completed: usize,
+    job: Option<JobHandle>,
+    /// The job changed since it was last saved
+    job_dirty: bool,
+}
 
-    let digest = match hash.function {
-        HashAlgorithm::Sha1 => calc_hash::<Sha1>(reader)?,
-        HashAlgorithm::Sha256 => calc_hash::<Sha256>(reader)?,
-        HashAlgorithm::Sha512 => calc_hash::<Sha512>(reader)?,
-    };
+impl Scheduler {
+    fn save_job(&mut self) {
+        if let Some(job) = &self.job {
+            if self.job_dirty {
+                job.save();
+                self.job_dirty = false;
+            }
+        }
+    }
 
-    if digest != hash.hash {
-        bail!("invalid hash: {}", hash.hash);
+    fn finish_job(&mut self) {
+        if let Some(job) = &self.job {
+            job.finish();
+            self.job_dirty = false;
+        }
     }
+}
 
-    Ok(())
+/// State shared by the workers of a running queue.
+struct Shared {
+    ctx: Context,
+    scheduler: Mutex<Scheduler>,
+    slot_freed: Condvar,
+    counter: ByteCounter,
+    control: DownloadControl,
+    total: usize,
+    max_per_host: usize,
+    max_attempts: u32,
 }
 
 #[derive(Debug, Clone)]
-pub struct DownloadQueue(Vec<DownloadItem>);
+pub struct DownloadQueue {
+    items: Vec<DownloadItem>,
+    max_concurrent: usize,
+    max_per_host: usize,
+    max_attempts: u32,
+    job: Option<JobHandle>,
+    control: DownloadControl,
+}
 
 impl DownloadQueue {
     pub fn new(items: Vec<DownloadItem>) -> Self {
-        Self(items)
+        Self {
+            items,
+            max_concurrent: DEFAULT_MAX_CONCURRENT_DOWNLOADS,
+            max_per_host: DEFAULT_MAX_DOWNLOADS_PER_HOST,
+            max_attempts: DEFAULT_MAX_DOWNLOAD_ATTEMPTS,
+            job: None,
+            control: DownloadControl::default(),
+        }
+    }
+
+    /// Saves the queue to disk as an [`InstallJob`], so it can be resumed if the launcher exits
+    /// before it's done.
+    pub fn with_job(self, description: &str, instance: Option<&str>) -> Result<Self> {
+        let job = InstallJob::new(description, instance, JobPlan::Files, &self.items);
+        job.save()?;
+
+        Ok(self.attach_job(JobHandle::owned(job)))
+    }
+
+    pub(crate) fn attach_job(mut self, job: JobHandle) -> Self {
+        self.job = Some(job);
+        self
+    }
+
+    /// Shares the pause and cancel state with another queue.
+    pub(crate) fn with_control(mut self, control: DownloadControl) -> Self {
+        self.control = control;
+        self
+    }
+
+    pub fn with_limits(mut self, max_concurrent: usize, max_per_host: usize) -> Self {
+        self.max_concurrent = max_concurrent.max(1);
+        self.max_per_host = max_per_host.max(1);
+        self
+    }
+
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
     }
 
     pub fn len(&self) -> usize {
-        self.0.len()
+        self.items.len()
     }
 
-    pub fn download_next(&mut self) -> Result<bool> {
-        if let Some(item) = self.0.pop() {
-            item.download_file()?;
-            Ok(true)
-        } else {
-            Ok(false)
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Returns a handle to pause, resume or cancel the queue once it's started.
+    pub fn control(&self) -> DownloadControl {
+        self.control.clone()
+    }
+
+    /// Downloads every item on a pool of worker threads.
+    ///
+    /// Events are sent in the order the items complete, so `completed` always increases by one.
+    /// Items are retried up to `max_attempts` times, and only those that still fail are reported
+    /// with `DownloadEvent::Failed`; the rest of the queue keeps going.
+    /// A `DownloadEvent::Progress` is sent every `PROGRESS_INTERVAL` while workers are running,
+    /// and a final `DownloadEvent::Finished` once every worker has exited.
+    /// If the queue is cancelled through its [`DownloadControl`], items in flight are dropped
+    /// along with their partial files, and `DownloadEvent::Cancelled` is sent instead.
+    pub fn start(self, ctx: Context) -> Receiver<DownloadEvent> {
+        let (tx, rx) = mpsc::channel();
+
+        let total = self.items.len();
+        let bytes_total = self.items.iter().filter_map(|item| item.size).sum::<u64>();
+        let workers = self.max_concurrent.min(total).max(1);
+
+        let shared = Arc::new(Shared {
+            ctx,
+            scheduler: Mutex::new(Scheduler {
+                pending: self.items.into_iter().enumerate().collect(),
+                active_per_host: HashMap::new(),
+                completed: 0,
+                job: self.job,
+                job_dirty: false,
+            }),
+            slot_freed: Condvar::new(),
+            counter: ByteCounter::default(),
+            control: self.control,
+            total,
+            max_per_host: self.max_per_host,
+            max_attempts: self.max_attempts,
+        });
+
+        thread::spawn(move || {
+            let handles = (0..workers)
+                .map(|_| {
+                    let shared = Arc::clone(&shared);
+                    let tx = tx.clone();
+
+                    thread::spawn(move || run_worker(&shared, &tx))
+                })
+                .collect::<Vec<_>>();
+            let counter = &shared.counter;
+
+            let mut last_transferred = 0;
+            let mut bytes_per_second = 0.0;
+
+            while !handles.iter().all(|handle| handle.is_finished()) {
+                thread::sleep(PROGRESS_INTERVAL);
+
+                let transferred = counter.transferred.load(Ordering::Relaxed);
+                let skipped = counter.skipped.load(Ordering::Relaxed);
+
+                // exponential moving average, so the speed doesn't jump around between ticks
+                let instant =
+                    (transferred - last_transferred) as f64 / PROGRESS_INTERVAL.as_secs_f64();
+                bytes_per_second = bytes_per_second * 0.7 + instant * 0.3;
+                last_transferred = transferred;
+
+                let bytes_downloaded = transferred + skipped;
+                let bytes_total = bytes_total.max(bytes_downloaded);
+                let eta = if bytes_per_second >= 1.0 {
+                    let remaining = (bytes_total - bytes_downloaded) as f64;
+                    Some(Duration::from_secs_f64(remaining / bytes_per_second))
+                } else {
+                    None
+                };
+
+                let items_completed = {
+                    let mut state = shared.scheduler.lock().unwrap();
+                    state.save_job();
+                    state.completed
+                };
+
+                let _ = tx.send(DownloadEvent::Progress(DownloadProgress {
+                    items_completed,
+                    items_total: total,
+                    bytes_downloaded,
+                    bytes_total,
+                    bytes_per_second,
+                    eta,
+                }));
+            }
+
+            for handle in handles {
+                let _ = handle.join();
+            }
+
+            shared.scheduler.lock().unwrap().finish_job();
+
+            if shared.control.is_cancelled() {
+                let _ = tx.send(DownloadEvent::Cancelled);
+            } else {
+                let _ = tx.send(DownloadEvent::Finished);
+            }
+        });
+
+        rx
+    }
+}
+
+fn run_worker(shared: &Shared, tx: &Sender<DownloadEvent>) {
+    let lock = &shared.scheduler;
+    let max_per_host = shared.max_per_host;
+    let total = shared.total;
+
+    loop {
+        // blocks while paused
+        if shared.control.checkpoint().is_err() {
+            return;
+        }
+
+        // wait for an item whose host still has a free slot
+        let (index, item, host) = {
+            let mut guard = lock.lock().unwrap();
+
+            loop {
+                let state = &mut *guard;
+
+                if state.pending.is_empty() || shared.control.is_cancelled() {
+                    return;
+                }
+
+                let position = state.pending.iter().position(|(_, item)| {
+                    let active = state.active_per_host.get(get_host(&item.url));
+                    active.copied().unwrap_or(0) < max_per_host
+                });
+
+                if let Some(position) = position {
+                    let (index, item) = state.pending.remove(position).unwrap();
+                    let host = get_host(&item.url).to_string();
+                    *state.active_per_host.entry(host.clone()).or_insert(0) += 1;
+
+                    break (index, item, host);
+                }
+
+                guard = shared.slot_freed.wait(guard).unwrap();
+            }
+        };
+
+        let existed = item.path.exists();
+        let result = item.download_file_with_retries(
+            &shared.ctx,
+            &shared.counter,
+            &shared.control,
+            shared.max_attempts,
+        );
+
+        {
+            let mut state = lock.lock().unwrap();
+
+            if let Some(active) = state.active_per_host.get_mut(&host) {
+                *active -= 1;
+            }
+
+            // neither completed nor failed, it stays pending in the job
+            if let Err(DownloadError::Cancelled) = result {
+                drop(state);
+                shared.slot_freed.notify_all();
+                continue;
+            }
+
+            state.completed += 1;
+            let completed = state.completed;
+
+            if let Some(job) = &state.job {
+                let status = match &result {
+                    Ok(()) if existed => ItemStatus::Skipped,
+                    Ok(()) => ItemStatus::Done,
+                    Err(_) => ItemStatus::Failed,
+                };
+                job.set_status(index, status);
+                state.job_dirty = true;
+            }
+
+            // send while holding the lock so events arrive in completion order
+            let event = match result {
+                Ok(()) => DownloadEvent::Completed {
+                    index,
+                    completed,
+                    total,
+                },
+                Err(error) => DownloadEvent::Failed {
+                    index,
+                    completed,
+                    total,
+                    url: item.url,
+                    error,
+                },
+            };
+            let _ = tx.send(event);
         }
+
+        shared.slot_freed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::endpoints::Endpoints;
+    use crate::transport::MemoryTransport;
+
+    #[test]
+    fn unhashed_json_falls_back_to_mirrors() {
+        let dir = tempfile::tempdir().unwrap();
+        let transport = Arc::new(MemoryTransport::new());
+        transport.add_error("https://example.com/version.json", "connection refused");
+        transport.add_response(
+            "https://mirror.example.com/version.json",
+            200,
+            vec![],
+            r#"{"id": "1.20.4"}"#,
+        );
+        let ctx = Context::with_transport(transport.clone(), Endpoints::default())
+            .with_cache(cache::MetaCache::new(dir.path().join("cache")));
+
+        let item = DownloadItem {
+            url: "https://example.com/version.json".to_string(),
+            mirrors: vec!["https://mirror.example.com/version.json".to_string()],
+            path: dir.path().join("meta").join("version.json"),
+            hash: None,
+            size: None,
+            extract: None,
+        };
+
+        let json = item.download_json::<serde_json::Value>(&ctx).unwrap();
+
+        assert_eq!(json["id"], "1.20.4");
+        assert_eq!(
+            fs::read_to_string(&item.path).unwrap(),
+            r#"{"id": "1.20.4"}"#
+        );
+        assert_eq!(transport.requests().len(), 2);
+    }
+
+    #[test]
+    fn cancelling_between_retries_removes_the_partial_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let transport = Arc::new(MemoryTransport::new());
+        transport.add_error("https://example.com/file", "connection reset");
+        let ctx = Context::with_transport(transport.clone(), Endpoints::default());
+
+        // the first attempt fails, and the wait before the second one gets cancelled
+        let control = DownloadControl::default();
+        control.cancel();
+
+        let item = DownloadItem {
+            url: "https://example.com/file".to_string(),
+            mirrors: vec![],
+            path: dir.path().join("file"),
+            hash: None,
+            size: None,
+            extract: None,
+        };
+        fs::write(item.part_path(), b"partial").unwrap();
+
+        let result = item.download_file_with_retries(&ctx, &ByteCounter::default(), &control, 3);
+
+        assert!(matches!(result, Err(DownloadError::Cancelled)));
+        assert!(!item.part_path().exists());
+        assert_eq!(transport.requests().len(), 1);
+    }
+
+    #[test]
+    fn ignored_ranges_take_back_the_skipped_bytes() {
+        let dir = tempfile::tempdir().unwrap();
+        let transport = Arc::new(MemoryTransport::new());
+        transport.add_response("https://example.com/file", 200, vec![], "complete");
+        let ctx = Context::with_transport(transport.clone(), Endpoints::default());
+
+        let item = DownloadItem {
+            url: "https://example.com/file".to_string(),
+            mirrors: vec![],
+            path: dir.path().join("file"),
+            hash: None,
+            size: Some(8),
+            extract: None,
+        };
+        fs::write(item.part_path(), b"comp").unwrap();
+
+        let counter = ByteCounter::default();
+        item.download_file_with_retries(&ctx, &counter, &DownloadControl::default(), 1)
+            .unwrap();
+
+        assert_eq!(fs::read(&item.path).unwrap(), b"complete");
+        assert_eq!(counter.skipped.load(Ordering::Relaxed), 0);
+        assert_eq!(counter.transferred.load(Ordering::Relaxed), 8);
+        assert!(transport.requests()[0]
+            .headers
+            .contains(&("Range".to_string(), "bytes=4-".to_string())));
     }
 }
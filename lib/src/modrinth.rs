@@ -1,12 +1,22 @@
 // SPDX-FileCopyrightText: 2023 Manuel Quarneti <manuelquarneti@protonmail.com>
 // SPDX-License-Identifier: GPL-3.0-only
 
-use std::{fs, io::BufReader, path::Path};
+use std::{
+    fs,
+    io::{self, BufReader},
+    path::{Component, Path, PathBuf},
+};
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use serde::Deserialize;
+use tempfile::TempDir;
 
-use crate::{DownloadItem, Hash, HashAlgorithm, AGENT};
+use crate::jobs::JobPlan;
+use crate::tasks::{Slot, TaskGraph};
+use crate::{
+    blocking, cache, fabric, store, vanilla_installer, Context, DownloadItem, ExtractOptions, Hash,
+    HashAlgorithm,
+};
 
 #[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct Project {
@@ -22,14 +32,13 @@ pub struct Projects {
     pub hits: Vec<Project>,
 }
 
-pub async fn search_modpacks(query: &str) -> Result<Projects> {
+pub async fn search_modpacks(ctx: Context, query: &str) -> Result<Projects> {
     let url = format!(
-        "https://api.modrinth.com/v2/search?query={query}&facets=[[\"categories:fabric\"],[\"project_type:modpack\"]]&limit=20",
+        "{}/v2/search?query={query}&facets=[[\"categories:fabric\"],[\"project_type:modpack\"]]&limit=20",
+        ctx.endpoints().modrinth_api
     );
 
-    let resp = AGENT.get(&url).call()?.into_json()?;
-
-    Ok(resp)
+    blocking::run(move || ctx.get_json_cached(&url, cache::MEDIUM_TTL)).await
 }
 
 #[derive(Deserialize)]
@@ -42,6 +51,7 @@ pub struct File {
     pub hashes: Hashes,
     pub url: String,
     pub filename: String,
+    pub size: u64,
 }
 
 #[derive(Deserialize)]
@@ -50,81 +60,321 @@ pub struct Version {
     pub files: Vec<File>,
 }
 
-pub async fn get_versions(modpack_id: &str) -> Result<Vec<Version>> {
-    let url = format!("https://api.modrinth.com/v2/project/{modpack_id}/version");
+pub async fn get_versions(ctx: Context, modpack_id: &str) -> Result<Vec<Version>> {
+    let url = format!(
+        "{}/v2/project/{modpack_id}/version",
+        ctx.endpoints().modrinth_api
+    );
+
+    blocking::run(move || ctx.get_json_cached(&url, cache::MEDIUM_TTL)).await
+}
+
+#[derive(Deserialize)]
+struct IndexFile {
+    path: String,
+    hashes: Hashes,
+    downloads: Vec<String>,
+    #[serde(rename = "fileSize")]
+    file_size: u64,
+}
+
+impl IndexFile {
+    /// Where the file goes in `dest_dir`.
+    ///
+    /// The path comes from the pack, so it may only name something inside the instance.
+    fn get_path(&self, dest_dir: &Path) -> Result<PathBuf> {
+        let path = Path::new(&self.path);
+
+        let is_relative = path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)));
+
+        if !is_relative || path.as_os_str().is_empty() {
+            bail!("Modpack file escapes the instance: {}", self.path);
+        }
+
+        Ok(dest_dir.join(path))
+    }
+}
+
+#[derive(Deserialize)]
+struct Dependencies {
+    minecraft: String,
+    #[serde(rename = "fabric-loader")]
+    fabric_loader: Option<String>,
+}
+
+const INDEX_FILE: &str = "modrinth.index.json";
+
+/// modrinth.index.json
+#[derive(Deserialize)]
+struct Index {
+    files: Vec<IndexFile>,
+    dependencies: Dependencies,
+}
+
+fn get_download_items(files: Vec<IndexFile>, dest_dir: &Path) -> Result<Vec<DownloadItem>> {
+    files
+        .into_iter()
+        .map(|file| {
+            Ok(DownloadItem {
+                url: file.downloads[0].to_owned(),
+                mirrors: file.downloads[1..].to_vec(),
+                path: file.get_path(dest_dir)?,
+                hash: Some(Hash {
+                    function: HashAlgorithm::Sha512,
+                    hash: file.hashes.sha512,
+                }),
+                size: Some(file.file_size),
+                extract: None,
+            })
+        })
+        .collect()
+}
+
+/// The files of the modpack installed in `dest_dir`, none if it isn't a modpack.
+pub(crate) fn get_installed_items(dest_dir: &Path) -> Result<Vec<DownloadItem>> {
+    let path = dest_dir.join(INDEX_FILE);
+    if !path.exists() {
+        return Ok(vec![]);
+    }
 
-    let resp = AGENT.get(&url).call()?.into_json()?;
+    let index = serde_json::from_reader::<_, Index>(BufReader::new(fs::File::open(path)?))?;
 
-    Ok(resp)
+    get_download_items(index.files, dest_dir)
 }
 
-pub fn install_version(version: &Version, dest_dir: &Path) -> Result<Vec<DownloadItem>> {
-    let tmp_dir = tempfile::tempdir()?;
+fn copy_dir(src: &Path, dest: &Path) -> io::Result<()> {
+    fs::create_dir_all(dest)?;
+
+    for entry in src.read_dir()? {
+        let entry = entry?;
+        let dest = dest.join(entry.file_name());
 
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &dest)?;
+        } else {
+            fs::copy(entry.path(), dest)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// A graph installing a modpack version into `dest_dir`: the pack itself, the files it lists, its
+/// overrides, and the Minecraft version and Fabric loader it needs.
+pub fn install_version(version: &Version, dest_dir: &Path) -> TaskGraph {
     let file = &version.files[0];
 
-    let hash = Hash {
-        function: HashAlgorithm::Sha512,
-        hash: file.hashes.sha512.to_owned(),
+    install_pack(&file.url, &file.hashes.sha512, file.size, dest_dir)
+}
+
+/// Like [`install_version`], from the pack file alone, so an interrupted install can be resumed.
+pub(crate) fn install_pack(url: &str, sha512: &str, size: u64, dest_dir: &Path) -> TaskGraph {
+    let mut graph = TaskGraph::new();
+    let pack = DownloadItem {
+        url: url.to_string(),
+        mirrors: vec![],
+        path: PathBuf::new(),
+        hash: Some(Hash {
+            function: HashAlgorithm::Sha512,
+            hash: sha512.to_string(),
+        }),
+        size: Some(size),
+        extract: Some(ExtractOptions::default()),
     };
 
-    DownloadItem {
-        url: file.url.to_owned(),
-        path: tmp_dir.path().to_path_buf(),
-        hash: Some(hash),
-        extract: true,
-    }
-    .download_file()?;
+    let tmp_dir = Slot::<TempDir>::new();
+    let items = Slot::<Vec<DownloadItem>>::new();
+    // the mods go to the store, so that's where they take up space
+    let stored_items = Slot::<Vec<DownloadItem>>::new();
+    let minecraft = Slot::new();
+    let fabric = Slot::new();
+
+    let download_pack = graph.add("Download modpack", &[], {
+        let tmp_dir = tmp_dir.clone();
+        let items = items.clone();
+        let stored_items = stored_items.clone();
+        let minecraft = minecraft.clone();
+        let fabric = fabric.clone();
+        let dest_dir = dest_dir.to_path_buf();
+
+        move |task| {
+            let dir = tempfile::tempdir()?;
+            let pack_dir = dir.path().join("pack");
+
+            DownloadItem {
+                path: pack_dir.clone(),
+                ..pack.clone()
+            }
+            .download_file(task.ctx())?;
+
+            let index = BufReader::new(fs::File::open(pack_dir.join(INDEX_FILE))?);
+            let index = serde_json::from_reader::<_, Index>(index)?;
 
-    let mut items = Vec::new();
+            let files = get_download_items(index.files, &dest_dir)?;
 
-    // parse modrinth.index.json
-    {
-        #[derive(Deserialize)]
-        struct File {
-            path: String,
-            hashes: Hashes,
-            downloads: Vec<String>,
+            // kept in the instance, to repair its files later
+            fs::create_dir_all(&dest_dir)?;
+            fs::copy(pack_dir.join(INDEX_FILE), dest_dir.join(INDEX_FILE))?;
+
+            stored_items.set(
+                files
+                    .iter()
+                    .map(|item| store::stored(item).unwrap_or_else(|| item.clone()))
+                    .collect(),
+            );
+            items.set(files);
+            minecraft.set(index.dependencies.minecraft);
+            fabric.set(index.dependencies.fabric_loader);
+            tmp_dir.set(dir);
+
+            Ok(())
         }
+    });
+
+    let version = vanilla_installer::add_tasks(
+        &mut graph,
+        minecraft.clone(),
+        None,
+        None,
+        Some(stored_items),
+        &[download_pack],
+    );
 
-        #[derive(Deserialize)]
-        struct Dependencies {
-            minecraft: String,
-            #[serde(rename = "fabric-loader")]
-            fabric_loader: Option<String>,
+    graph.add("Copy overrides", &[download_pack], {
+        let dest_dir = dest_dir.to_path_buf();
+
+        move |_| {
+            let overrides = tmp_dir.get()?.path().join("pack").join("overrides");
+
+            if overrides.exists() {
+                copy_dir(&overrides, &dest_dir)?;
+            }
+
+            Ok(())
         }
+    });
+
+    graph.add("Download mods", &[version.check_space], move |task| {
+        task.download_stored(items.get()?.to_vec())
+    });
 
-        #[derive(Deserialize)]
-        struct Index {
-            files: Vec<File>,
-            dependencies: Dependencies,
+    fabric::add_tasks(&mut graph, minecraft, fabric, &[download_pack]);
+
+    graph.with_plan(JobPlan::Modpack {
+        url: url.to_string(),
+        sha512: sha512.to_string(),
+        size,
+        dest_dir: dest_dir.to_path_buf(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::cache::MetaCache;
+    use crate::endpoints::Endpoints;
+    use crate::transport::MemoryTransport;
+
+    fn file(path: &str) -> IndexFile {
+        IndexFile {
+            path: path.to_string(),
+            hashes: Hashes {
+                sha512: String::new(),
+            },
+            downloads: vec![],
+            file_size: 0,
         }
+    }
 
-        let index = tmp_dir.path().join("modrinth.index.json");
-        let index = BufReader::new(fs::File::open(index)?);
-        let index = serde_json::from_reader::<_, Vec<File>>(index)?;
+    #[test]
+    fn index_paths_stay_in_the_instance() {
+        let dest_dir = Path::new("instance");
 
-        for file in index {
-            let hash = Hash {
-                function: HashAlgorithm::Sha512,
-                hash: file.hashes.sha512.to_owned(),
-            };
+        assert_eq!(
+            file("mods/sodium.jar").get_path(dest_dir).unwrap(),
+            dest_dir.join("mods").join("sodium.jar")
+        );
 
-            items.push(DownloadItem {
-                url: file.downloads[0].to_owned(),
-                path: dest_dir.join(file.path),
-                hash: Some(hash),
-                extract: false,
-            });
+        for path in [
+            "",
+            "../../.bashrc",
+            "mods/../../x",
+            "/etc/passwd",
+            "./mods/a.jar",
+        ] {
+            assert!(file(path).get_path(dest_dir).is_err(), "{}", path);
         }
     }
 
-    // copy overrides to dest_dir
-    for r#override in tmp_dir.path().join("overrides").read_dir()? {
-        let r#override = r#override?;
-        let dest = dest_dir.join(r#override.file_name());
-        fs::copy(r#override.path(), dest)?;
+    #[test]
+    fn versions_come_from_the_api() {
+        let dir = tempfile::tempdir().unwrap();
+        let transport = Arc::new(MemoryTransport::new());
+        transport.add_json(
+            "https://api.modrinth.com/v2/project/fabulously-optimized/version",
+            &json!([{
+                "name": "5.12.0",
+                "files": [{
+                    "hashes": { "sha512": "abc" },
+                    "url": "https://cdn.modrinth.com/fo.mrpack",
+                    "filename": "fo.mrpack",
+                    "size": 42,
+                }],
+            }]),
+        );
+        let ctx = Context::with_transport(transport, Endpoints::default())
+            .with_cache(MetaCache::new(dir.path()));
+
+        let versions = blocking::block_on(get_versions(ctx, "fabulously-optimized")).unwrap();
+
+        assert_eq!(versions.len(), 1);
+        assert_eq!(versions[0].name, "5.12.0");
+        assert_eq!(
+            versions[0].files[0].url,
+            "https://cdn.modrinth.com/fo.mrpack"
+        );
+        assert_eq!(versions[0].files[0].hashes.sha512, "abc");
     }
 
-    Ok(items)
+    #[test]
+    fn installed_pack_files_can_be_checked() {
+        use sha2::{Digest, Sha512};
+
+        let dir = tempfile::tempdir().unwrap();
+        assert!(get_installed_items(dir.path()).unwrap().is_empty());
+
+        let hash = hex::encode(Sha512::digest(b"mod"));
+        let index = json!({
+            "files": [
+                {
+                    "path": "mods/a.jar",
+                    "hashes": { "sha512": hash },
+                    "downloads": ["https://cdn.modrinth.com/a.jar"],
+                    "fileSize": 3,
+                },
+                {
+                    "path": "mods/b.jar",
+                    "hashes": { "sha512": hash },
+                    "downloads": ["https://cdn.modrinth.com/b.jar"],
+                    "fileSize": 3,
+                },
+            ],
+            "dependencies": { "minecraft": "1.20.4" },
+        });
+        fs::write(dir.path().join(INDEX_FILE), index.to_string()).unwrap();
+        fs::create_dir(dir.path().join("mods")).unwrap();
+        fs::write(dir.path().join("mods").join("a.jar"), b"mad").unwrap();
+
+        let items = get_installed_items(dir.path()).unwrap();
+
+        assert_eq!(items[0].path, dir.path().join("mods").join("a.jar"));
+        assert_eq!(items[0].check().unwrap(), crate::ItemState::Corrupted);
+        assert_eq!(items[1].check().unwrap(), crate::ItemState::Missing);
+    }
 }
@@ -1,18 +1,103 @@
 // SPDX-FileCopyrightText: 2023 Manuel Quarneti <manuelquarneti@protonmail.com>
 // SPDX-License-Identifier: GPL-3.0-only
 
+use std::collections::HashMap;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::{env, io};
 
+use anyhow::{bail, Result};
 use directories::ProjectDirs;
-use once_cell::sync::Lazy;
+use once_cell::sync::{Lazy, OnceCell};
 
-pub static BASE_DIR: Lazy<PathBuf> = Lazy::new(|| {
-    let dir = ProjectDirs::from("eu", "mq1", "CrabLauncher")
-        .unwrap()
-        .data_dir()
-        .to_path_buf();
+/// Environment variable overriding the data directory
+pub const BASE_DIR_ENV: &str = "CRABLAUNCHER_DIR";
+
+/// Keeps the data next to the executable when it's there. It can hold a path to use instead,
+/// relative to the executable.
+const PORTABLE_FILE: &str = "portable.txt";
+
+/// Where the data directory was migrated to, kept in the config directory
+const LOCATION_FILE: &str = "data-dir.txt";
+
+/// Where the data directory comes from, in order of precedence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BaseDirSource {
+    Flag,
+    Env,
+    Portable,
+    Migrated,
+    Default,
+}
+
+static BASE_DIR_OVERRIDE: OnceCell<PathBuf> = OnceCell::new();
+
+/// Sets the data directory, as passed on the command line.
+///
+/// Returns `false` if it's too late, because the data directory is already in use.
+pub fn set_base_dir(dir: PathBuf) -> bool {
+    Lazy::get(&BASE).is_none() && BASE_DIR_OVERRIDE.set(dir).is_ok()
+}
+
+fn project_dirs() -> ProjectDirs {
+    ProjectDirs::from("eu", "mq1", "CrabLauncher").unwrap()
+}
+
+fn get_portable_file() -> Option<PathBuf> {
+    let exe = env::current_exe().ok()?;
+    let path = exe.parent()?.join(PORTABLE_FILE);
+
+    path.exists().then_some(path)
+}
+
+fn get_location_file() -> PathBuf {
+    project_dirs().config_dir().join(LOCATION_FILE)
+}
+
+/// Reads a path from `file`, relative to the directory holding it. Empty files give `None`.
+fn read_path_file(file: &Path) -> Option<PathBuf> {
+    let contents = fs::read_to_string(file).ok()?;
+    let path = contents.trim();
+
+    if path.is_empty() {
+        return None;
+    }
+
+    Some(file.parent()?.join(path))
+}
+
+fn resolve_base_dir() -> (PathBuf, BaseDirSource) {
+    if let Some(dir) = BASE_DIR_OVERRIDE.get() {
+        return (dir.clone(), BaseDirSource::Flag);
+    }
+
+    if let Some(dir) = env::var_os(BASE_DIR_ENV).filter(|dir| !dir.is_empty()) {
+        return (PathBuf::from(dir), BaseDirSource::Env);
+    }
+
+    if let Some(file) = get_portable_file() {
+        let dir = read_path_file(&file).unwrap_or_else(|| file.with_file_name("data"));
+        return (dir, BaseDirSource::Portable);
+    }
+
+    if let Some(dir) = read_path_file(&get_location_file()) {
+        return (dir, BaseDirSource::Migrated);
+    }
+
+    (
+        project_dirs().data_dir().to_path_buf(),
+        BaseDirSource::Default,
+    )
+}
+
+static BASE: Lazy<(PathBuf, BaseDirSource)> = Lazy::new(resolve_base_dir);
 
+pub fn get_base_dir_source() -> BaseDirSource {
+    BASE.1
+}
+
+pub static BASE_DIR: Lazy<PathBuf> = Lazy::new(|| {
+    let dir = BASE.0.clone();
     fs::create_dir_all(&dir).unwrap();
 
     dir
@@ -26,7 +111,23 @@ pub static META_DIR: Lazy<PathBuf> = Lazy::new(|| {
 });
 
 pub static ASSETS_DIR: Lazy<PathBuf> = Lazy::new(|| {
-    let dir = BASE_DIR.join("../../assets");
+    let dir = BASE_DIR.join("assets");
+
+    // older versions kept the assets two levels above the default data directory
+    if get_base_dir_source() == BaseDirSource::Default && !dir.exists() {
+        let legacy = BASE_DIR.join("../../assets");
+
+        if legacy.exists() {
+            if let Err(error) = fs::rename(&legacy, &dir) {
+                println!(
+                    "Failed to move the assets into {}: {}",
+                    dir.display(),
+                    error
+                );
+            }
+        }
+    }
+
     fs::create_dir_all(&dir).unwrap();
 
     dir
@@ -39,12 +140,207 @@ pub static LIBRARIES_DIR: Lazy<PathBuf> = Lazy::new(|| {
     dir
 });
 
+pub static NATIVES_DIR: Lazy<PathBuf> = Lazy::new(|| {
+    let dir = BASE_DIR.join("natives");
+    fs::create_dir_all(&dir).unwrap();
+
+    dir
+});
+
 pub static RUNTIMES_DIR: Lazy<PathBuf> = Lazy::new(|| {
     let dir = BASE_DIR.join("runtimes");
     fs::create_dir_all(&dir).unwrap();
 
     dir
 });
+
+pub static CACHE_DIR: Lazy<PathBuf> = Lazy::new(|| {
+    let dir = BASE_DIR.join("cache");
+    fs::create_dir_all(&dir).unwrap();
+
+    dir
+});
+
+pub static JOBS_DIR: Lazy<PathBuf> = Lazy::new(|| {
+    let dir = BASE_DIR.join("jobs");
+    fs::create_dir_all(&dir).unwrap();
+
+    dir
+});
+
+pub static STORE_DIR: Lazy<PathBuf> = Lazy::new(|| {
+    let dir = BASE_DIR.join("store");
+    fs::create_dir_all(&dir).unwrap();
+
+    dir
+});
+
 pub static SETTINGS_PATH: Lazy<PathBuf> = Lazy::new(|| BASE_DIR.join("settings.toml"));
 
 pub static ACCOUNTS_PATH: Lazy<PathBuf> = Lazy::new(|| BASE_DIR.join("accounts.toml"));
+
+/// Copies `src` into `dest`, keeping files that are hardlinked together linked in the copy.
+#[cfg_attr(not(unix), allow(unused_variables))]
+fn copy_tree(src: &Path, dest: &Path, links: &mut HashMap<(u64, u64), PathBuf>) -> io::Result<()> {
+    fs::create_dir_all(dest)?;
+
+    for entry in fs::read_dir(src)? {
+        let entry = entry?;
+        let src = entry.path();
+        let dest = dest.join(entry.file_name());
+        let file_type = entry.file_type()?;
+
+        // links are recreated as they are, following them could copy things outside the tree
+        if file_type.is_symlink() {
+            copy_symlink(&src, &dest)?;
+            continue;
+        }
+
+        if file_type.is_dir() {
+            copy_tree(&src, &dest, links)?;
+            continue;
+        }
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+
+            let metadata = entry.metadata()?;
+            if metadata.nlink() > 1 {
+                let key = (metadata.dev(), metadata.ino());
+
+                if let Some(first) = links.get(&key) {
+                    fs::hard_link(first, &dest)?;
+                    continue;
+                }
+                links.insert(key, dest.clone());
+            }
+        }
+
+        fs::copy(&src, &dest)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn copy_symlink(src: &Path, dest: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(fs::read_link(src)?, dest)
+}
+
+#[cfg(windows)]
+fn copy_symlink(src: &Path, dest: &Path) -> io::Result<()> {
+    let target = fs::read_link(src)?;
+
+    if fs::metadata(src).is_ok_and(|metadata| metadata.is_dir()) {
+        std::os::windows::fs::symlink_dir(target, dest)
+    } else {
+        std::os::windows::fs::symlink_file(target, dest)
+    }
+}
+
+/// Moves the whole data directory, assets included, to `dest`, and uses it from the next start.
+///
+/// Nothing else may have touched the data directory yet, so this has to run before the
+/// launcher loads anything. `dest` must not exist or be empty.
+pub fn migrate(dest: &Path) -> Result<()> {
+    // pulls in assets left in the old place
+    Lazy::force(&ASSETS_DIR);
+
+    let src = &*BASE_DIR;
+    let dest = env::current_dir()?.join(dest);
+
+    if dest.starts_with(src) {
+        bail!("Can't move {} into itself", src.display());
+    }
+
+    if dest.exists() {
+        if fs::read_dir(&dest)?.next().is_some() {
+            bail!("{} is not empty", dest.display());
+        }
+
+        fs::remove_dir(&dest)?;
+    }
+
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    println!("moving {} to {}", src.display(), dest.display());
+
+    // renaming only works on the same filesystem
+    if fs::rename(src, &dest).is_err() {
+        if let Err(error) = copy_tree(src, &dest, &mut HashMap::new()) {
+            // don't leave a half copy behind, the data is still in the old place
+            let _ = fs::remove_dir_all(&dest);
+            return Err(error.into());
+        }
+
+        fs::remove_dir_all(src)?;
+    }
+
+    match get_base_dir_source() {
+        BaseDirSource::Portable => {
+            if let Some(file) = get_portable_file() {
+                fs::write(file, dest.to_string_lossy().as_bytes())?;
+            }
+        }
+        BaseDirSource::Migrated | BaseDirSource::Default => {
+            let file = get_location_file();
+
+            if let Some(parent) = file.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(file, dest.to_string_lossy().as_bytes())?;
+        }
+        BaseDirSource::Flag | BaseDirSource::Env => {
+            println!(
+                "Pass --data-dir or set {} to {} from now on",
+                BASE_DIR_ENV,
+                dest.display()
+            );
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn copy_tree_keeps_links() {
+        use std::os::unix::fs::{symlink, MetadataExt};
+
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        let dest = dir.path().join("dest");
+
+        fs::create_dir_all(src.join("instances")).unwrap();
+        fs::write(src.join("store"), b"jar").unwrap();
+        fs::hard_link(src.join("store"), src.join("instances").join("mod.jar")).unwrap();
+        symlink("../store", src.join("instances").join("link")).unwrap();
+        // would copy the whole filesystem if it were followed
+        symlink("/", src.join("root")).unwrap();
+
+        copy_tree(&src, &dest, &mut HashMap::new()).unwrap();
+
+        assert_eq!(
+            fs::read_link(dest.join("instances").join("link")).unwrap(),
+            Path::new("../store")
+        );
+        assert_eq!(fs::read_link(dest.join("root")).unwrap(), Path::new("/"));
+        assert_eq!(
+            fs::metadata(dest.join("store")).unwrap().ino(),
+            fs::metadata(dest.join("instances").join("mod.jar"))
+                .unwrap()
+                .ino()
+        );
+        assert_eq!(
+            fs::read(dest.join("instances").join("link")).unwrap(),
+            b"jar"
+        );
+    }
+}
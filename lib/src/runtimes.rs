@@ -0,0 +1,335 @@
+// SPDX-FileCopyrightText: 2023 Manuel Quarneti <manuelquarneti@protonmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail, Result};
+use version_compare::Version;
+
+use crate::instances::Instance;
+use crate::jobs::JobPlan;
+use crate::paths::RUNTIMES_DIR;
+use crate::tasks::TaskGraph;
+use crate::{adoptium, space, Context, DownloadItem};
+
+/// A Java runtime in `RUNTIMES_DIR/<major>/<release>-jre`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Runtime {
+    pub major: u32,
+    /// Like `jdk-17.0.9+9`, what instances are pinned to
+    pub release: String,
+    pub vendor: String,
+    /// Like `17.0.9`
+    pub version: String,
+    pub arch: String,
+    pub dir: PathBuf,
+}
+
+/// Reads the `KEY="value"` lines of the `release` file every JDK and JRE ships.
+fn read_release_file(path: &Path) -> HashMap<String, String> {
+    let Ok(contents) = fs::read_to_string(path) else {
+        return HashMap::new();
+    };
+
+    contents
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| (key.to_string(), value.trim_matches('"').to_string()))
+        .collect()
+}
+
+impl Runtime {
+    fn load(major: u32, dir: PathBuf) -> Option<Self> {
+        let name = dir.file_name()?.to_string_lossy().to_string();
+        let release = name.strip_suffix("-jre").unwrap_or(&name).to_string();
+
+        let runtime = Self {
+            major,
+            release,
+            vendor: String::new(),
+            version: String::new(),
+            arch: String::new(),
+            dir,
+        };
+
+        let mut info = read_release_file(&runtime.get_home().join("release"));
+        let version = info.remove("JAVA_VERSION").unwrap_or_else(|| {
+            let release = runtime.release.trim_start_matches("jdk-");
+            release.trim_start_matches("jdk").to_string()
+        });
+
+        Some(Self {
+            vendor: info.remove("IMPLEMENTOR").unwrap_or_default(),
+            version,
+            arch: info.remove("OS_ARCH").unwrap_or_default(),
+            ..runtime
+        })
+    }
+
+    /// The `JAVA_HOME` of this runtime.
+    pub fn get_home(&self) -> PathBuf {
+        if cfg!(target_os = "macos") {
+            self.dir.join("Contents").join("Home")
+        } else {
+            self.dir.clone()
+        }
+    }
+
+    pub fn get_java_path(&self) -> PathBuf {
+        let java = if cfg!(target_os = "windows") {
+            "java.exe"
+        } else {
+            "java"
+        };
+
+        self.get_home().join("bin").join(java)
+    }
+
+    /// Newer releases first.
+    fn cmp_version(&self, other: &Self) -> Ordering {
+        match (Version::from(&self.version), Version::from(&other.version)) {
+            (Some(a), Some(b)) => b.compare(a).ord().unwrap_or(Ordering::Equal),
+            _ => other.release.cmp(&self.release),
+        }
+    }
+
+    /// Deletes the runtime, and the directory of its major version if nothing's left there.
+    pub fn delete(&self) -> Result<()> {
+        println!("removing Java runtime: {}", self.release);
+        fs::remove_dir_all(&self.dir)?;
+
+        if let Some(parent) = self.dir.parent() {
+            if fs::read_dir(parent)?.next().is_none() {
+                fs::remove_dir(parent)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The installed runtimes of `major`, newest first.
+pub fn list_major(major: u32) -> Result<Vec<Runtime>> {
+    let dir = RUNTIMES_DIR.join(major.to_string());
+    if !dir.exists() {
+        return Ok(vec![]);
+    }
+
+    let mut runtimes = Vec::new();
+    for entry in fs::read_dir(&dir)? {
+        let entry = entry?;
+
+        if !entry.file_type()?.is_dir() {
+            continue;
+        }
+
+        // skip half extracted archives
+        if let Some(runtime) = Runtime::load(major, entry.path()) {
+            if runtime.get_java_path().exists() {
+                runtimes.push(runtime);
+            }
+        }
+    }
+
+    runtimes.sort_by(Runtime::cmp_version);
+
+    Ok(runtimes)
+}
+
+/// Every installed runtime, by major version and then newest first.
+pub fn list() -> Result<Vec<Runtime>> {
+    let mut majors = Vec::new();
+    for entry in fs::read_dir(&*RUNTIMES_DIR)? {
+        let entry = entry?;
+
+        if let Ok(major) = entry.file_name().to_string_lossy().parse::<u32>() {
+            majors.push(major);
+        }
+    }
+    majors.sort_unstable();
+
+    let mut runtimes = Vec::new();
+    for major in majors {
+        runtimes.extend(list_major(major)?);
+    }
+
+    Ok(runtimes)
+}
+
+/// The runtime to launch with: the pinned `release` if any, otherwise the newest of `major`.
+pub fn find(major: u32, release: Option<&str>) -> Result<Runtime> {
+    let mut runtimes = list_major(major)?;
+
+    match release {
+        Some(release) => runtimes
+            .into_iter()
+            .find(|runtime| runtime.release == release)
+            .ok_or_else(|| anyhow!("{} is not installed", release)),
+        None if runtimes.is_empty() => bail!("Java {} is not installed", major),
+        None => Ok(runtimes.remove(0)),
+    }
+}
+
+/// What's needed to run `major`, or the pinned `release`.
+///
+/// Any installed runtime will do, so nothing is asked of the API unless it's missing.
+pub fn install(ctx: &Context, major: u32, release: Option<&str>) -> Result<Vec<DownloadItem>> {
+    if find(major, release).is_ok() {
+        return Ok(vec![]);
+    }
+
+    if ctx.is_offline() {
+        bail!(
+            "Java {} is not installed and the launcher is offline",
+            major
+        );
+    }
+
+    let release = match release {
+        Some(release) => adoptium::get_release(ctx, release)?,
+        None => adoptium::get_latest(ctx, major)?,
+    };
+
+    Ok(vec![release.get_download_item(major)])
+}
+
+/// A newer release of an installed major version.
+#[derive(Debug, Clone)]
+pub struct RuntimeUpdate {
+    pub major: u32,
+    /// The newest release installed
+    pub installed: String,
+    pub latest: String,
+}
+
+/// Asks the API about the newest release of every installed major version.
+pub fn check_updates(ctx: &Context) -> Result<Vec<RuntimeUpdate>> {
+    if ctx.is_offline() {
+        bail!("Can't check for Java updates while offline");
+    }
+
+    let mut updates = Vec::new();
+    let mut checked = Vec::new();
+
+    // newest first, so the first runtime of each major is the one to compare
+    for runtime in list()? {
+        if checked.contains(&runtime.major) {
+            continue;
+        }
+        checked.push(runtime.major);
+
+        let latest = adoptium::get_latest(ctx, runtime.major)?;
+        if latest.name != runtime.release {
+            updates.push(RuntimeUpdate {
+                major: runtime.major,
+                installed: runtime.release,
+                latest: latest.name,
+            });
+        }
+    }
+
+    Ok(updates)
+}
+
+/// Installs the newest release of `major` next to the current ones.
+///
+/// Instances that aren't pinned switch to it, the older releases stay until they're unused.
+pub fn update(ctx: &Context, major: u32) -> Result<Vec<DownloadItem>> {
+    let items = vec![adoptium::get_latest(ctx, major)?.get_download_item(major)];
+    space::check(&items)?;
+
+    Ok(items)
+}
+
+/// A graph running [`update`] for `major`.
+pub fn download_update(major: u32) -> TaskGraph {
+    let mut graph = TaskGraph::new();
+    graph.add(&format!("Install Java {}", major), &[], move |task| {
+        task.download(update(task.ctx(), major)?)
+    });
+
+    graph.with_plan(JobPlan::JavaUpdate { major })
+}
+
+/// The runtime an instance needs.
+#[derive(Debug, Clone)]
+pub struct InstanceRuntime {
+    pub instance: String,
+    pub major: u32,
+    pub pinned: Option<String>,
+    /// The directory of the runtime it launches with, unless that's missing
+    pub dir: Option<PathBuf>,
+}
+
+/// The installed runtimes and which instance uses which.
+#[derive(Debug, Clone, Default)]
+pub struct RuntimeOverview {
+    pub runtimes: Vec<Runtime>,
+    pub instances: Vec<InstanceRuntime>,
+}
+
+impl RuntimeOverview {
+    /// Works out what `instances` run on from the metadata on disk.
+    ///
+    /// Fails if the Java version of an instance can't be told, as every runtime might be in use.
+    pub fn load(instances: &[(String, Instance)]) -> Result<Self> {
+        let mut overview = Self {
+            runtimes: list()?,
+            instances: Vec::new(),
+        };
+
+        for (name, instance) in instances {
+            let major = instance
+                .get_java_major()
+                .map_err(|error| anyhow!("Can't tell which Java {} uses: {}", name, error))?;
+            let pinned = instance.java_release.clone();
+            let dir = find(major, pinned.as_deref())
+                .ok()
+                .map(|runtime| runtime.dir);
+
+            overview.instances.push(InstanceRuntime {
+                instance: name.clone(),
+                major,
+                pinned,
+                dir,
+            });
+        }
+
+        overview
+            .instances
+            .sort_by(|a, b| a.instance.cmp(&b.instance));
+
+        Ok(overview)
+    }
+
+    /// The instances launching with `runtime`.
+    pub fn get_users(&self, runtime: &Runtime) -> Vec<&str> {
+        self.instances
+            .iter()
+            .filter(|instance| instance.dir.as_ref() == Some(&runtime.dir))
+            .map(|instance| instance.instance.as_str())
+            .collect()
+    }
+
+    /// The installed releases an instance could be pinned to.
+    pub fn get_releases(&self, major: u32) -> Vec<&str> {
+        self.runtimes
+            .iter()
+            .filter(|runtime| runtime.major == major)
+            .map(|runtime| runtime.release.as_str())
+            .collect()
+    }
+
+    /// The runtimes no instance launches with.
+    pub fn get_unused(&self) -> Vec<&Runtime> {
+        self.runtimes
+            .iter()
+            .filter(|runtime| self.get_users(runtime).is_empty())
+            .collect()
+    }
+}
@@ -6,17 +6,42 @@ use std::fs;
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
 
+use crate::endpoints::Endpoints;
 use crate::paths::SETTINGS_PATH;
+use crate::proxy::ProxySettings;
+use crate::{
+    DEFAULT_MAX_CONCURRENT_DOWNLOADS, DEFAULT_MAX_DOWNLOADS_PER_HOST, DEFAULT_MAX_DOWNLOAD_ATTEMPTS,
+};
 
 #[derive(Serialize, Deserialize)]
+#[serde(default)]
 pub struct Settings {
     pub check_for_updates: bool,
+    /// Never go to the network, even if it's reachable
+    pub offline: bool,
+    pub max_concurrent_downloads: usize,
+    pub max_downloads_per_host: usize,
+    pub max_download_attempts: u32,
+    /// In KB/s, unlimited if not set
+    pub max_download_speed: Option<u64>,
+    /// In KB/s, used instead while a game is running
+    pub max_background_download_speed: Option<u64>,
+    pub endpoints: Endpoints,
+    pub proxy: ProxySettings,
 }
 
 impl Default for Settings {
     fn default() -> Self {
         Self {
             check_for_updates: true,
+            offline: false,
+            max_concurrent_downloads: DEFAULT_MAX_CONCURRENT_DOWNLOADS,
+            max_downloads_per_host: DEFAULT_MAX_DOWNLOADS_PER_HOST,
+            max_download_attempts: DEFAULT_MAX_DOWNLOAD_ATTEMPTS,
+            max_download_speed: None,
+            max_background_download_speed: None,
+            endpoints: Endpoints::default(),
+            proxy: ProxySettings::default(),
         }
     }
 }
@@ -0,0 +1,70 @@
+// SPDX-FileCopyrightText: 2023 Manuel Quarneti <manuelquarneti@protonmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{fs, io};
+
+use anyhow::{bail, Result};
+
+use crate::paths::BASE_DIR;
+use crate::DownloadItem;
+
+/// Archives unpack to about this many times their size.
+const EXTRACT_RATIO: u64 = 3;
+
+/// A byte count for people to read, like `1.5 MB`.
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1000.0 && unit < UNITS.len() - 1 {
+        value /= 1000.0;
+        unit += 1;
+    }
+
+    format!("{value:.1} {}", UNITS[unit])
+}
+
+/// The bytes `items` still need on disk, leaving out what's already there.
+///
+/// Items with an unknown size don't count.
+pub fn required(items: &[DownloadItem]) -> u64 {
+    items
+        .iter()
+        .filter(|item| !item.path.exists())
+        .map(|item| {
+            let size = item.size.unwrap_or(0);
+            let downloaded = fs::metadata(item.part_path()).map_or(0, |metadata| metadata.len());
+            let remaining = size.saturating_sub(downloaded);
+
+            // the archive stays around until it's unpacked
+            if item.extract.is_some() {
+                remaining + size * EXTRACT_RATIO
+            } else {
+                remaining
+            }
+        })
+        .sum()
+}
+
+/// The free space on the filesystem holding `BASE_DIR`.
+pub fn available() -> io::Result<u64> {
+    fs4::available_space(&*BASE_DIR)
+}
+
+/// Fails if `items` don't fit on the disk, so nothing is left half installed.
+pub fn check(items: &[DownloadItem]) -> Result<()> {
+    let required = required(items);
+    let available = available()?;
+
+    if required > available {
+        bail!(
+            "Not enough disk space: {} needed, but only {} free in {}",
+            format_size(required),
+            format_size(available),
+            BASE_DIR.display()
+        );
+    }
+
+    Ok(())
+}
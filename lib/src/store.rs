@@ -0,0 +1,162 @@
+// SPDX-FileCopyrightText: 2023 Manuel Quarneti <manuelquarneti@protonmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::paths::STORE_DIR;
+use crate::{verify, DownloadItem, Hash, HashAlgorithm};
+
+/// Where the file with `hash` is kept in the store.
+pub fn path(hash: &Hash) -> PathBuf {
+    let function = match hash.function {
+        HashAlgorithm::Sha1 => "sha1",
+        HashAlgorithm::Sha256 => "sha256",
+        HashAlgorithm::Sha512 => "sha512",
+    };
+
+    STORE_DIR
+        .join(function)
+        .join(hash.get_path().to_ascii_lowercase())
+}
+
+/// The same item, downloaded into the store instead of its own path.
+///
+/// Only plain files with a known hash can be stored.
+pub fn stored(item: &DownloadItem) -> Option<DownloadItem> {
+    match (&item.hash, item.extract) {
+        (Some(hash), None) => Some(DownloadItem {
+            path: path(hash),
+            ..item.clone()
+        }),
+        _ => None,
+    }
+}
+
+/// Puts the stored file with `hash` at `dest`, replacing whatever else is there.
+///
+/// Hardlinks are tried first, so every instance shares the same copy. Across filesystems the
+/// file is copied instead, which is a cheap reflink where the filesystem supports it.
+pub fn link(hash: &Hash, dest: &Path) -> io::Result<()> {
+    link_from(&path(hash), hash, dest, |src, dest| {
+        fs::hard_link(src, dest)
+    })
+}
+
+/// Like [`link`], with the stored file at `src` and the way to hardlink it passed in.
+fn link_from(
+    src: &Path,
+    hash: &Hash,
+    dest: &Path,
+    hard_link: impl Fn(&Path, &Path) -> io::Result<()>,
+) -> io::Result<()> {
+    if is_same_file(src, dest) {
+        return Ok(());
+    }
+
+    // a copy made across filesystems is just as good, as long as nothing changed it
+    if dest.exists() && verify::hash_file(dest, &hash.function)?.eq_ignore_ascii_case(&hash.hash) {
+        return Ok(());
+    }
+
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    // linked or copied next to the destination first, then moved over it in one step
+    let mut tmp = dest.as_os_str().to_os_string();
+    tmp.push(".part");
+    let tmp = PathBuf::from(tmp);
+
+    match fs::remove_file(&tmp) {
+        Err(error) if error.kind() != io::ErrorKind::NotFound => return Err(error),
+        _ => {}
+    }
+
+    if hard_link(src, &tmp).is_err() {
+        fs::copy(src, &tmp)?;
+    }
+
+    fs::rename(&tmp, dest)
+}
+
+#[cfg(unix)]
+fn is_same_file(a: &Path, b: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    match (fs::metadata(a), fs::metadata(b)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+// without inodes to compare, the hash check has to do
+#[cfg(not(unix))]
+fn is_same_file(_: &Path, _: &Path) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::verify::Hasher;
+
+    fn stored_file(dir: &Path, contents: &[u8]) -> (PathBuf, Hash) {
+        let path = dir.join("store");
+        fs::write(&path, contents).unwrap();
+
+        let mut hasher = Hasher::new(&HashAlgorithm::Sha1);
+        hasher.update(contents);
+        let hash = Hash {
+            hash: hasher.finalize(),
+            function: HashAlgorithm::Sha1,
+        };
+
+        (path, hash)
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn instances_share_one_copy() {
+        use std::os::unix::fs::MetadataExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let (src, hash) = stored_file(dir.path(), b"jar");
+        let a = dir.path().join("a").join("mods").join("mod.jar");
+        let b = dir.path().join("b").join("mods").join("mod.jar");
+
+        // an outdated file is replaced by the stored one
+        fs::create_dir_all(b.parent().unwrap()).unwrap();
+        fs::write(&b, b"old").unwrap();
+
+        for dest in [&a, &b, &a] {
+            link_from(&src, &hash, dest, |src, dest| fs::hard_link(src, dest)).unwrap();
+        }
+
+        let ino = |path: &Path| fs::metadata(path).unwrap().ino();
+        assert_eq!(ino(&a), ino(&src));
+        assert_eq!(ino(&b), ino(&src));
+        assert_eq!(fs::metadata(&src).unwrap().nlink(), 3);
+    }
+
+    #[test]
+    fn falls_back_to_a_copy() {
+        let dir = tempfile::tempdir().unwrap();
+        let (src, hash) = stored_file(dir.path(), b"jar");
+        let dest = dir.path().join("mods").join("mod.jar");
+        let cross_device = |_: &Path, _: &Path| Err(io::Error::other("cross-device link"));
+
+        link_from(&src, &hash, &dest, cross_device).unwrap();
+        assert_eq!(fs::read(&dest).unwrap(), b"jar");
+
+        // a changed copy is replaced, an intact one is kept
+        fs::write(&dest, b"changed").unwrap();
+        link_from(&src, &hash, &dest, cross_device).unwrap();
+        link_from(&src, &hash, &dest, cross_device).unwrap();
+
+        assert_eq!(fs::read(&dest).unwrap(), b"jar");
+        assert!(!dir.path().join("mods").join("mod.jar.part").exists());
+    }
+}
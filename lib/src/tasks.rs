@@ -0,0 +1,478 @@
+// SPDX-FileCopyrightText: 2023 Manuel Quarneti <manuelquarneti@protonmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::jobs::{InstallJob, JobHandle, JobPlan};
+use crate::store;
+use crate::{
+    Context, DownloadControl, DownloadError, DownloadEvent, DownloadItem, DownloadProgress,
+    DownloadQueue, DEFAULT_MAX_CONCURRENT_DOWNLOADS, DEFAULT_MAX_DOWNLOADS_PER_HOST,
+    DEFAULT_MAX_DOWNLOAD_ATTEMPTS,
+};
+
+pub type TaskId = usize;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TaskState {
+    Pending,
+    Running,
+    Done,
+    Failed(String),
+    /// Didn't run because a task it depends on failed
+    Skipped,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TaskEvent {
+    Started(TaskId),
+    Progress(TaskId, DownloadProgress),
+    /// A file of a download task failed for good, the rest of the task keeps going
+    ItemFailed {
+        task: TaskId,
+        url: String,
+        error: String,
+    },
+    Done(TaskId),
+    Failed(TaskId, String),
+    Skipped(TaskId),
+    Cancelled(TaskId),
+    /// Every task that could run did
+    Finished,
+}
+
+/// The output of a task, read by the tasks depending on it.
+pub struct Slot<T>(Arc<Mutex<Option<Arc<T>>>>);
+
+impl<T> Clone for Slot<T> {
+    fn clone(&self) -> Self {
+        Self(Arc::clone(&self.0))
+    }
+}
+
+impl<T> Default for Slot<T> {
+    fn default() -> Self {
+        Self(Arc::new(Mutex::new(None)))
+    }
+}
+
+impl<T> Slot<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A slot that's known before the graph starts.
+    pub fn filled(value: T) -> Self {
+        let slot = Self::new();
+        slot.set(value);
+        slot
+    }
+
+    pub fn set(&self, value: T) {
+        *self.0.lock().unwrap() = Some(Arc::new(value));
+    }
+
+    pub fn get(&self) -> Result<Arc<T>> {
+        self.0
+            .lock()
+            .unwrap()
+            .clone()
+            .ok_or_else(|| anyhow!("A task ran before the task it depends on"))
+    }
+}
+
+/// How the download tasks of a graph run their queues.
+#[derive(Debug, Clone)]
+struct QueueSettings {
+    control: DownloadControl,
+    max_concurrent: usize,
+    max_per_host: usize,
+    max_attempts: u32,
+    job: Option<Arc<Mutex<InstallJob>>>,
+}
+
+/// What a running task gets to work with.
+pub struct TaskContext {
+    id: TaskId,
+    ctx: Context,
+    tx: Sender<TaskEvent>,
+    settings: QueueSettings,
+}
+
+impl TaskContext {
+    pub fn ctx(&self) -> &Context {
+        &self.ctx
+    }
+
+    /// Downloads `items`, reporting progress as this task's own.
+    pub fn download(&self, items: Vec<DownloadItem>) -> Result<()> {
+        let job = match &self.settings.job {
+            Some(job) => Some(JobHandle::shared(job, &items)?),
+            None => None,
+        };
+
+        let mut queue = DownloadQueue::new(items);
+        if let Some(job) = job {
+            queue = queue.attach_job(job);
+        }
+
+        self.run_queue(queue)
+    }
+
+    /// Downloads `items` into the shared store, then links them into place.
+    ///
+    /// Files already in the store aren't downloaded again. Items without a hash are downloaded
+    /// straight to their path.
+    pub fn download_stored(&self, items: Vec<DownloadItem>) -> Result<()> {
+        let mut downloads: Vec<DownloadItem> = Vec::new();
+        let mut links = Vec::new();
+
+        for item in items {
+            match store::stored(&item) {
+                Some(stored) => {
+                    if !downloads
+                        .iter()
+                        .any(|download| download.path == stored.path)
+                    {
+                        downloads.push(stored);
+                    }
+                    links.push((item.hash.unwrap(), item.path));
+                }
+                None => downloads.push(item),
+            }
+        }
+
+        self.download(downloads)?;
+
+        for (hash, path) in links {
+            store::link(&hash, &path)?;
+        }
+
+        Ok(())
+    }
+
+    fn run_queue(&self, queue: DownloadQueue) -> Result<()> {
+        let settings = &self.settings;
+        let queue = queue
+            .with_limits(settings.max_concurrent, settings.max_per_host)
+            .with_max_attempts(settings.max_attempts)
+            .with_control(settings.control.clone());
+
+        let mut failed = 0;
+
+        for event in queue.start(self.ctx.clone()) {
+            match event {
+                DownloadEvent::Progress(progress) => {
+                    let _ = self.tx.send(TaskEvent::Progress(self.id, progress));
+                }
+                DownloadEvent::Failed { url, error, .. } => {
+                    failed += 1;
+                    let _ = self.tx.send(TaskEvent::ItemFailed {
+                        task: self.id,
+                        url,
+                        error: error.to_string(),
+                    });
+                }
+                DownloadEvent::Cancelled => bail!(DownloadError::Cancelled),
+                DownloadEvent::Completed { .. } | DownloadEvent::Finished => {}
+            }
+        }
+
+        if failed > 0 {
+            bail!("{} files could not be downloaded", failed);
+        }
+
+        Ok(())
+    }
+}
+
+type Run = dyn Fn(&TaskContext) -> Result<()> + Send + Sync;
+
+#[derive(Clone)]
+struct Task {
+    name: String,
+    deps: Vec<TaskId>,
+    run: Arc<Run>,
+}
+
+/// An install, split into tasks that run as soon as the tasks they depend on are done.
+///
+/// Tasks can only depend on tasks added before them, so the graph can't have cycles.
+/// Clones share the state of every task: starting a graph again only runs the tasks that
+/// didn't succeed the last time.
+#[derive(Clone)]
+pub struct TaskGraph {
+    tasks: Vec<Task>,
+    states: Arc<Mutex<Vec<TaskState>>>,
+    settings: QueueSettings,
+    /// Saved with the job, to build the graph again when it's resumed
+    plan: JobPlan,
+}
+
+impl Default for TaskGraph {
+    fn default() -> Self {
+        Self {
+            tasks: Vec::new(),
+            states: Arc::new(Mutex::new(Vec::new())),
+            settings: QueueSettings {
+                control: DownloadControl::default(),
+                max_concurrent: DEFAULT_MAX_CONCURRENT_DOWNLOADS,
+                max_per_host: DEFAULT_MAX_DOWNLOADS_PER_HOST,
+                max_attempts: DEFAULT_MAX_DOWNLOAD_ATTEMPTS,
+                job: None,
+            },
+            plan: JobPlan::Graph,
+        }
+    }
+}
+
+impl TaskGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A graph with a single task downloading `queue`.
+    pub fn from_queue(name: &str, queue: DownloadQueue) -> Self {
+        let mut graph = Self::new();
+        graph.add(name, &[], move |task| task.run_queue(queue.clone()));
+        graph
+    }
+
+    pub fn add(
+        &mut self,
+        name: &str,
+        deps: &[TaskId],
+        run: impl Fn(&TaskContext) -> Result<()> + Send + Sync + 'static,
+    ) -> TaskId {
+        let id = self.tasks.len();
+        assert!(
+            deps.iter().all(|dep| *dep < id),
+            "tasks can only depend on earlier tasks"
+        );
+
+        self.tasks.push(Task {
+            name: name.to_string(),
+            deps: deps.to_vec(),
+            run: Arc::new(run),
+        });
+        self.states.lock().unwrap().push(TaskState::Pending);
+
+        id
+    }
+
+    /// Records every file the graph downloads as an [`InstallJob`], so it can be resumed or
+    /// rolled back if the launcher exits before the graph is done.
+    ///
+    /// Only graphs with a plan can be resumed, the others can still be rolled back. The job is
+    /// saved once the graph starts, so a graph that never runs leaves nothing behind.
+    pub fn with_job(self, description: &str, instance: Option<&str>) -> Self {
+        let job = InstallJob::new(description, instance, self.plan.clone(), &[]);
+
+        self.attach_job(job)
+    }
+
+    pub(crate) fn with_plan(mut self, plan: JobPlan) -> Self {
+        self.plan = plan;
+        self
+    }
+
+    #[cfg(test)]
+    pub(crate) fn plan(&self) -> &JobPlan {
+        &self.plan
+    }
+
+    /// Reports to `job`, which is only removed once every task is done.
+    pub(crate) fn attach_job(mut self, job: InstallJob) -> Self {
+        self.settings.job = Some(Arc::new(Mutex::new(job)));
+        self
+    }
+
+    pub fn with_limits(mut self, max_concurrent: usize, max_per_host: usize) -> Self {
+        self.settings.max_concurrent = max_concurrent.max(1);
+        self.settings.max_per_host = max_per_host.max(1);
+        self
+    }
+
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.settings.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.tasks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
+
+    pub fn name(&self, id: TaskId) -> &str {
+        &self.tasks[id].name
+    }
+
+    pub fn states(&self) -> Vec<TaskState> {
+        self.states.lock().unwrap().clone()
+    }
+
+    /// Returns a handle to pause, resume or cancel every download of the graph.
+    pub fn control(&self) -> DownloadControl {
+        self.settings.control.clone()
+    }
+
+    /// A copy of the graph to run what's left of it again, even after it was cancelled.
+    pub fn retry(&self) -> Self {
+        let mut graph = self.clone();
+        graph.settings.control = DownloadControl::default();
+        graph
+    }
+
+    /// Runs every task that isn't done yet, each on its own thread.
+    ///
+    /// When a task fails, the tasks depending on it are skipped and the rest keep going.
+    /// `TaskEvent::Finished` is sent once nothing is left to run.
+    pub fn start(self, ctx: Context) -> Receiver<TaskEvent> {
+        let (tx, rx) = mpsc::channel();
+
+        thread::spawn(move || self.run(ctx, tx));
+
+        rx
+    }
+
+    fn set_state(&self, id: TaskId, state: TaskState) {
+        self.states.lock().unwrap()[id] = state;
+    }
+
+    fn run(self, ctx: Context, tx: Sender<TaskEvent>) {
+        let (done_tx, done_rx) = mpsc::channel();
+        let control = &self.settings.control;
+
+        if let Some(job) = &self.settings.job {
+            let job = job.lock().unwrap();
+
+            if let Err(error) = job.save() {
+                println!("Failed to save job {}: {}", job.id, error);
+            }
+        }
+
+        for state in self.states.lock().unwrap().iter_mut() {
+            if *state != TaskState::Done {
+                *state = TaskState::Pending;
+            }
+        }
+
+        let mut running = 0;
+
+        loop {
+            if !control.is_cancelled() {
+                let ready = {
+                    let states = self.states.lock().unwrap();
+
+                    (0..self.tasks.len())
+                        .filter(|id| states[*id] == TaskState::Pending)
+                        .filter(|id| {
+                            self.tasks[*id]
+                                .deps
+                                .iter()
+                                .all(|dep| states[*dep] == TaskState::Done)
+                        })
+                        .collect::<Vec<_>>()
+                };
+
+                for id in ready {
+                    self.set_state(id, TaskState::Running);
+                    let _ = tx.send(TaskEvent::Started(id));
+                    running += 1;
+
+                    let run = Arc::clone(&self.tasks[id].run);
+                    let task = TaskContext {
+                        id,
+                        ctx: ctx.clone(),
+                        tx: tx.clone(),
+                        settings: self.settings.clone(),
+                    };
+                    let done_tx = done_tx.clone();
+
+                    thread::spawn(move || {
+                        let result = run(&task);
+                        let _ = done_tx.send((id, result));
+                    });
+                }
+            }
+
+            if running == 0 {
+                break;
+            }
+
+            let Ok((id, result)) = done_rx.recv() else {
+                break;
+            };
+            running -= 1;
+
+            let (state, event) = match result {
+                Ok(()) => (TaskState::Done, TaskEvent::Done(id)),
+                Err(_) if control.is_cancelled() => {
+                    (TaskState::Cancelled, TaskEvent::Cancelled(id))
+                }
+                Err(error) => {
+                    println!("Task failed: {}: {:#}", self.tasks[id].name, error);
+                    let error = format!("{:#}", error);
+                    (
+                        TaskState::Failed(error.clone()),
+                        TaskEvent::Failed(id, error),
+                    )
+                }
+            };
+            self.set_state(id, state);
+            let _ = tx.send(event);
+        }
+
+        // whatever is still pending depends on a failed task, or was never started
+        for id in 0..self.tasks.len() {
+            let mut states = self.states.lock().unwrap();
+
+            if states[id] == TaskState::Pending {
+                let (state, event) = if control.is_cancelled() {
+                    (TaskState::Cancelled, TaskEvent::Cancelled(id))
+                } else {
+                    (TaskState::Skipped, TaskEvent::Skipped(id))
+                };
+
+                states[id] = state;
+                let _ = tx.send(event);
+            }
+        }
+
+        self.finish_job();
+
+        let _ = tx.send(TaskEvent::Finished);
+    }
+
+    /// Forgets the job once every task is done, or saves it for next time.
+    fn finish_job(&self) {
+        let Some(job) = &self.settings.job else {
+            return;
+        };
+
+        let job = job.lock().unwrap();
+        let done = self
+            .states
+            .lock()
+            .unwrap()
+            .iter()
+            .all(|state| *state == TaskState::Done);
+
+        let result = if done { job.remove() } else { job.save() };
+        if let Err(error) = result {
+            println!("Failed to update job {}: {}", job.id, error);
+        }
+    }
+}
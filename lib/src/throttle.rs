@@ -0,0 +1,171 @@
+// SPDX-FileCopyrightText: 2023 Manuel Quarneti <manuelquarneti@protonmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{DownloadControl, DownloadError};
+
+#[derive(Debug)]
+struct Bucket {
+    /// Bytes that can be read right away, negative when workers are in debt
+    available: f64,
+    refilled_at: Instant,
+}
+
+#[derive(Debug)]
+struct Inner {
+    /// Bytes per second, 0 for no limit
+    limit: AtomicU64,
+    /// Used instead of `limit` while a game is running
+    background_limit: AtomicU64,
+    games_running: AtomicUsize,
+    bucket: Mutex<Bucket>,
+}
+
+/// A download rate limit shared by every worker.
+///
+/// While a game is running, the background limit applies instead, if it's lower.
+/// Clones share the limits, so changing them applies to downloads already running.
+#[derive(Debug, Clone)]
+pub struct Bandwidth {
+    inner: Arc<Inner>,
+}
+
+impl Default for Bandwidth {
+    fn default() -> Self {
+        Self::new(None, None)
+    }
+}
+
+impl Bandwidth {
+    pub fn new(limit: Option<u64>, background_limit: Option<u64>) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                limit: AtomicU64::new(limit.unwrap_or(0)),
+                background_limit: AtomicU64::new(background_limit.unwrap_or(0)),
+                games_running: AtomicUsize::new(0),
+                bucket: Mutex::new(Bucket {
+                    available: 0.,
+                    refilled_at: Instant::now(),
+                }),
+            }),
+        }
+    }
+
+    /// Changes the limits in place, for every clone and running game.
+    pub fn set_limits(&self, limit: Option<u64>, background_limit: Option<u64>) {
+        let inner = &self.inner;
+
+        inner.limit.store(limit.unwrap_or(0), Ordering::Relaxed);
+        inner
+            .background_limit
+            .store(background_limit.unwrap_or(0), Ordering::Relaxed);
+    }
+
+    /// The limit in bytes per second that applies right now.
+    pub fn current_limit(&self) -> Option<u64> {
+        let load =
+            |limit: &AtomicU64| Some(limit.load(Ordering::Relaxed)).filter(|limit| *limit > 0);
+        let limit = load(&self.inner.limit);
+
+        if self.inner.games_running.load(Ordering::Relaxed) == 0 {
+            return limit;
+        }
+
+        match (limit, load(&self.inner.background_limit)) {
+            (Some(limit), Some(background)) => Some(limit.min(background)),
+            (limit, background) => limit.or(background),
+        }
+    }
+
+    /// Switches to the background limit until the returned guard is dropped.
+    pub fn game_started(&self) -> GameGuard {
+        self.inner.games_running.fetch_add(1, Ordering::Relaxed);
+
+        GameGuard {
+            inner: self.inner.clone(),
+        }
+    }
+
+    /// Accounts for `bytes` just read, sleeping until they fit in the limit.
+    ///
+    /// Also waits while the download is paused, and fails once it's cancelled.
+    pub(crate) fn throttle(
+        &self,
+        bytes: usize,
+        control: &DownloadControl,
+    ) -> Result<(), DownloadError> {
+        control.checkpoint()?;
+
+        let Some(limit) = self.current_limit().filter(|limit| *limit > 0) else {
+            return Ok(());
+        };
+        let limit = limit as f64;
+
+        let wait = {
+            let mut bucket = self.inner.bucket.lock().unwrap();
+
+            // allow bursts of up to a second worth of data
+            let now = Instant::now();
+            let elapsed = now.duration_since(bucket.refilled_at).as_secs_f64();
+            bucket.available = (bucket.available + elapsed * limit).min(limit);
+            bucket.refilled_at = now;
+
+            bucket.available -= bytes as f64;
+
+            if bucket.available < 0. {
+                Duration::from_secs_f64(-bucket.available / limit)
+            } else {
+                Duration::ZERO
+            }
+        };
+
+        if wait > Duration::ZERO {
+            control.sleep(wait)?;
+        }
+
+        control.checkpoint()
+    }
+}
+
+/// Keeps the background limit in place while a game is running.
+#[derive(Debug)]
+pub struct GameGuard {
+    inner: Arc<Inner>,
+}
+
+impl Drop for GameGuard {
+    fn drop(&mut self) {
+        self.inner.games_running.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_change_for_every_clone() {
+        let bandwidth = Bandwidth::new(Some(1000), None);
+        let running = bandwidth.clone();
+        let game = running.game_started();
+
+        bandwidth.set_limits(Some(4000), Some(2000));
+        assert_eq!(running.current_limit(), Some(2000));
+
+        drop(game);
+        assert_eq!(running.current_limit(), Some(4000));
+
+        bandwidth.set_limits(None, Some(2000));
+        assert_eq!(running.current_limit(), None);
+
+        let _game = bandwidth.game_started();
+        assert_eq!(running.current_limit(), Some(2000));
+    }
+}
@@ -0,0 +1,359 @@
+// SPDX-FileCopyrightText: 2023 Manuel Quarneti <manuelquarneti@protonmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    collections::HashMap,
+    fmt,
+    io::{self, Cursor, Read},
+    sync::Mutex,
+};
+
+use serde::de::DeserializeOwned;
+use ureq::{Agent, AgentBuilder};
+
+use crate::get_host;
+use crate::proxy::Proxy;
+
+const USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    Get,
+    Head,
+    Post,
+}
+
+impl Method {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+            Method::Post => "POST",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+    pub method: Method,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Option<Vec<u8>>,
+}
+
+impl Request {
+    pub fn new(method: Method, url: &str) -> Self {
+        Self {
+            method,
+            url: url.to_string(),
+            headers: Vec::new(),
+            body: None,
+        }
+    }
+
+    pub fn get(url: &str) -> Self {
+        Self::new(Method::Get, url)
+    }
+
+    pub fn post(url: &str) -> Self {
+        Self::new(Method::Post, url)
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn json(self, body: &serde_json::Value) -> Self {
+        let mut request = self
+            .header("Content-Type", "application/json")
+            .header("Accept", "application/json");
+        request.body = Some(body.to_string().into_bytes());
+        request
+    }
+}
+
+pub struct Response {
+    pub url: String,
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    body: Box<dyn Read + Send>,
+}
+
+impl fmt::Debug for Response {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Response")
+            .field("url", &self.url)
+            .field("status", &self.status)
+            .field("headers", &self.headers)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Response {
+    pub fn new(
+        url: &str,
+        status: u16,
+        headers: Vec<(String, String)>,
+        body: Box<dyn Read + Send>,
+    ) -> Self {
+        Self {
+            url: url.to_string(),
+            status,
+            headers,
+            body,
+        }
+    }
+
+    /// Returns the first header with the given name, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Turns 4xx and 5xx responses into errors.
+    pub fn error_for_status(self) -> Result<Self, HttpError> {
+        if self.status >= 400 {
+            Err(HttpError::Status {
+                url: self.url,
+                code: self.status,
+            })
+        } else {
+            Ok(self)
+        }
+    }
+
+    pub fn into_reader(self) -> Box<dyn Read + Send> {
+        self.body
+    }
+
+    pub fn into_bytes(mut self) -> io::Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        self.body.read_to_end(&mut bytes)?;
+
+        Ok(bytes)
+    }
+
+    pub fn into_json<T: DeserializeOwned>(self) -> io::Result<T> {
+        serde_json::from_reader(self.body)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+    }
+}
+
+#[derive(Debug)]
+pub enum HttpError {
+    Status {
+        url: String,
+        code: u16,
+    },
+    Transport {
+        url: String,
+        message: String,
+    },
+    /// The request wasn't sent because the launcher is in offline mode
+    Offline {
+        url: String,
+    },
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HttpError::Status { url, code } => write!(f, "{}: status code {}", url, code),
+            HttpError::Transport { url, message } => write!(f, "{}: {}", url, message),
+            HttpError::Offline { url } => write!(f, "{}: not available in offline mode", url),
+        }
+    }
+}
+
+impl std::error::Error for HttpError {}
+
+/// Sends HTTP requests for the rest of the library.
+///
+/// Error statuses are returned as regular responses, only failing to talk to the server at all
+/// is an error.
+pub trait Transport: Send + Sync {
+    fn send(&self, request: Request) -> Result<Response, HttpError>;
+}
+
+pub struct UreqTransport {
+    agent: Agent,
+    /// Used for the hosts the proxy is bypassed for
+    direct: Agent,
+    proxy: Option<Proxy>,
+}
+
+impl Default for UreqTransport {
+    fn default() -> Self {
+        Self::new(AgentBuilder::new())
+    }
+}
+
+impl UreqTransport {
+    pub fn new(builder: AgentBuilder) -> Self {
+        let agent = builder.user_agent(USER_AGENT).build();
+
+        Self {
+            agent: agent.clone(),
+            direct: agent,
+            proxy: None,
+        }
+    }
+
+    pub fn with_proxy(proxy: Proxy) -> anyhow::Result<Self> {
+        let agent = AgentBuilder::new()
+            .proxy(proxy.to_ureq()?)
+            .user_agent(USER_AGENT)
+            .build();
+
+        Ok(Self {
+            agent,
+            direct: AgentBuilder::new().user_agent(USER_AGENT).build(),
+            proxy: Some(proxy),
+        })
+    }
+
+    fn agent_for(&self, url: &str) -> &Agent {
+        match &self.proxy {
+            Some(proxy) => {
+                let host = get_host(url);
+                let host = host.rsplit_once(':').map_or(host, |(host, _)| host);
+
+                if proxy.bypasses(host) {
+                    &self.direct
+                } else {
+                    &self.agent
+                }
+            }
+            None => &self.agent,
+        }
+    }
+}
+
+impl Transport for UreqTransport {
+    fn send(&self, request: Request) -> Result<Response, HttpError> {
+        let mut req = self
+            .agent_for(&request.url)
+            .request(request.method.as_str(), &request.url);
+        for (name, value) in &request.headers {
+            req = req.set(name, value);
+        }
+
+        let result = match &request.body {
+            Some(body) => req.send_bytes(body),
+            None => req.call(),
+        };
+
+        let response = match result {
+            Ok(response) | Err(ureq::Error::Status(_, response)) => response,
+            Err(ureq::Error::Transport(transport)) => {
+                return Err(HttpError::Transport {
+                    url: request.url,
+                    message: transport.to_string(),
+                });
+            }
+        };
+
+        let headers = response
+            .headers_names()
+            .into_iter()
+            .filter_map(|name| {
+                let value = response.header(&name)?.to_string();
+                Some((name, value))
+            })
+            .collect();
+
+        Ok(Response::new(
+            &request.url,
+            response.status(),
+            headers,
+            Box::new(response.into_reader()),
+        ))
+    }
+}
+
+#[derive(Debug, Clone)]
+struct CannedResponse {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+/// Serves canned responses from memory and records every request it gets.
+///
+/// Unknown URLs get an empty 404 response.
+#[derive(Default)]
+pub struct MemoryTransport {
+    responses: Mutex<HashMap<String, Result<CannedResponse, String>>>,
+    requests: Mutex<Vec<Request>>,
+}
+
+impl MemoryTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_response(
+        &self,
+        url: &str,
+        status: u16,
+        headers: Vec<(String, String)>,
+        body: impl Into<Vec<u8>>,
+    ) {
+        let response = CannedResponse {
+            status,
+            headers,
+            body: body.into(),
+        };
+
+        self.responses
+            .lock()
+            .unwrap()
+            .insert(url.to_string(), Ok(response));
+    }
+
+    /// Makes requests to `url` fail like a dropped connection would.
+    pub fn add_error(&self, url: &str, message: &str) {
+        self.responses
+            .lock()
+            .unwrap()
+            .insert(url.to_string(), Err(message.to_string()));
+    }
+
+    pub fn add_json(&self, url: &str, body: &serde_json::Value) {
+        let headers = vec![("Content-Type".to_string(), "application/json".to_string())];
+        self.add_response(url, 200, headers, body.to_string());
+    }
+
+    /// Returns the requests received so far, oldest first.
+    pub fn requests(&self) -> Vec<Request> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+impl Transport for MemoryTransport {
+    fn send(&self, request: Request) -> Result<Response, HttpError> {
+        let response = self.responses.lock().unwrap().get(&request.url).cloned();
+        let url = request.url.clone();
+        self.requests.lock().unwrap().push(request);
+
+        let response = match response {
+            Some(Ok(response)) => response,
+            Some(Err(message)) => return Err(HttpError::Transport { url, message }),
+            None => CannedResponse {
+                status: 404,
+                headers: Vec::new(),
+                body: Vec::new(),
+            },
+        };
+
+        Ok(Response::new(
+            &url,
+            response.status,
+            response.headers,
+            Box::new(Cursor::new(response.body)),
+        ))
+    }
+}
@@ -5,31 +5,86 @@ use anyhow::Result;
 use serde::Deserialize;
 use version_compare::Version;
 
-use crate::AGENT;
+use crate::{blocking, Context};
 
-const LATEST_RELEASE_URL: &str = "https://api.github.com/repos/mq1/CrabLauncher/releases/latest";
-const RELEASES_BASE_URL: &str = "https://github.com/mq1/CrabLauncher/releases/tag/";
+const LATEST_RELEASE_PATH: &str = "/repos/mq1/CrabLauncher/releases/latest";
+const RELEASES_BASE_PATH: &str = "/mq1/CrabLauncher/releases/tag/";
 
 #[derive(Deserialize)]
 struct Release {
     tag_name: String,
 }
 
-async fn get_latest_release() -> Result<Release> {
-    let resp = AGENT.get(LATEST_RELEASE_URL).call()?.into_json()?;
+fn get_latest_release(ctx: &Context) -> Result<Release> {
+    let url = format!("{}{}", ctx.endpoints().github_api, LATEST_RELEASE_PATH);
+    let resp = ctx.get_json(&url)?;
 
     Ok(resp)
 }
 
-pub async fn check_for_updates() -> Result<Option<(String, String)>> {
-    let latest_release = get_latest_release().await?;
+pub async fn check_for_updates(ctx: Context) -> Result<Option<(String, String)>> {
+    let latest_release = blocking::run({
+        let ctx = ctx.clone();
+        move || get_latest_release(&ctx)
+    })
+    .await?;
     let latest_release = Version::from(&latest_release.tag_name).unwrap();
     let current_version = Version::from(env!("CARGO_PKG_VERSION")).unwrap();
 
     if latest_release > current_version {
-        let url = format!("{}{}", RELEASES_BASE_URL, latest_release);
+        let url = format!(
+            "{}{}{}",
+            ctx.endpoints().github,
+            RELEASES_BASE_PATH,
+            latest_release
+        );
         return Ok(Some((latest_release.to_string(), url)));
     }
 
     Ok(None)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::endpoints::Endpoints;
+    use crate::transport::MemoryTransport;
+
+    fn context_with_release(tag_name: &str) -> Context {
+        let transport = Arc::new(MemoryTransport::new());
+        transport.add_json(
+            "https://api.github.com/repos/mq1/CrabLauncher/releases/latest",
+            &json!({ "tag_name": tag_name }),
+        );
+
+        Context::with_transport(transport, Endpoints::default())
+    }
+
+    #[test]
+    fn newer_release_is_reported() {
+        let ctx = context_with_release("999.0.0");
+
+        let update = blocking::block_on(check_for_updates(ctx)).unwrap();
+
+        assert_eq!(
+            update,
+            Some((
+                "999.0.0".to_string(),
+                "https://github.com/mq1/CrabLauncher/releases/tag/999.0.0".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn older_release_is_ignored() {
+        let ctx = context_with_release("0.0.1");
+
+        let update = blocking::block_on(check_for_updates(ctx)).unwrap();
+
+        assert_eq!(update, None);
+    }
+}
@@ -0,0 +1,143 @@
+// SPDX-FileCopyrightText: 2023 Manuel Quarneti <manuelquarneti@protonmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::path::PathBuf;
+
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+
+use super::rules::{Argument, Environment};
+use super::SEPARATOR;
+use crate::paths::LIBRARIES_DIR;
+
+/// What the official launcher passes to the JVM for versions that don't list JVM arguments.
+const LEGACY_JVM_ARGUMENTS: &str = r#"[
+    {
+        "rules": [{ "action": "allow", "os": { "name": "osx" } }],
+        "value": ["-XstartOnFirstThread"]
+    },
+    {
+        "rules": [{ "action": "allow", "os": { "name": "windows" } }],
+        "value": "-XX:HeapDumpPath=MojangTricksIntelDriversForPerformance_javaw.exe_minecraft.exe.heapdump"
+    },
+    {
+        "rules": [{ "action": "allow", "os": { "arch": "x86" } }],
+        "value": "-Xss1M"
+    },
+    "-Djava.library.path=${natives_directory}",
+    "-Dminecraft.launcher.brand=${launcher_name}",
+    "-Dminecraft.launcher.version=${launcher_version}",
+    "-cp",
+    "${classpath}"
+]"#;
+
+pub(super) static LEGACY_JVM: Lazy<Vec<Argument>> =
+    Lazy::new(|| serde_json::from_str(LEGACY_JVM_ARGUMENTS).unwrap());
+
+/// The `arguments` of versions since 1.13.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct Arguments {
+    #[serde(default)]
+    pub game: Vec<Argument>,
+    #[serde(default)]
+    pub jvm: Vec<Argument>,
+}
+
+/// What the placeholders in launch arguments stand for.
+#[derive(Debug, Clone)]
+pub struct LaunchContext {
+    pub player_name: String,
+    pub uuid: String,
+    pub access_token: String,
+    pub xuid: String,
+    pub user_type: String,
+    pub version_name: String,
+    pub version_type: String,
+    pub game_directory: PathBuf,
+    pub assets_root: PathBuf,
+    /// The assets by name, for versions that can't read them from `assets_root`
+    pub game_assets: PathBuf,
+    pub assets_index_name: String,
+    pub natives_directory: PathBuf,
+    pub classpath: String,
+    /// Window size, the game picks one if unset
+    pub resolution: Option<(u32, u32)>,
+}
+
+impl LaunchContext {
+    fn get(&self, placeholder: &str) -> Option<String> {
+        let value = match placeholder {
+            "auth_player_name" => self.player_name.clone(),
+            "auth_uuid" => self.uuid.clone(),
+            "auth_access_token" => self.access_token.clone(),
+            "auth_session" => format!("token:{}:{}", self.access_token, self.uuid),
+            "auth_xuid" => self.xuid.clone(),
+            "user_type" => self.user_type.clone(),
+            "user_properties" => "{}".to_string(),
+            "clientid" => format!("{}/{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+            "version_name" => self.version_name.clone(),
+            "version_type" => self.version_type.clone(),
+            "game_directory" => self.game_directory.to_string_lossy().to_string(),
+            "assets_root" => self.assets_root.to_string_lossy().to_string(),
+            "game_assets" => self.game_assets.to_string_lossy().to_string(),
+            "assets_index_name" => self.assets_index_name.clone(),
+            "natives_directory" => self.natives_directory.to_string_lossy().to_string(),
+            "library_directory" => LIBRARIES_DIR.to_string_lossy().to_string(),
+            "classpath" => self.classpath.clone(),
+            "classpath_separator" => SEPARATOR.to_string(),
+            "launcher_name" => env!("CARGO_PKG_NAME").to_string(),
+            "launcher_version" => env!("CARGO_PKG_VERSION").to_string(),
+            "resolution_width" => self.resolution?.0.to_string(),
+            "resolution_height" => self.resolution?.1.to_string(),
+            _ => return None,
+        };
+
+        Some(value)
+    }
+
+    /// Replaces every `${placeholder}` in `argument`. Unknown ones are left as they are.
+    pub fn substitute(&self, argument: &str) -> String {
+        let mut result = String::new();
+        let mut rest = argument;
+
+        while let Some(start) = rest.find("${") {
+            let Some(end) = rest[start..].find('}') else {
+                break;
+            };
+            let placeholder = &rest[start + 2..start + end];
+
+            result.push_str(&rest[..start]);
+            match self.get(placeholder) {
+                Some(value) => result.push_str(&value),
+                None => {
+                    println!("unknown placeholder in launch arguments: {}", placeholder);
+                    result.push_str(&rest[start..start + end + 1]);
+                }
+            }
+
+            rest = &rest[start + end + 1..];
+        }
+
+        result.push_str(rest);
+        result
+    }
+
+    /// The environment conditional arguments are checked against.
+    pub fn environment(&self) -> Environment {
+        Environment::default().with_feature("has_custom_resolution", self.resolution.is_some())
+    }
+
+    /// Substitutes the arguments that apply here, in order.
+    pub fn resolve(&self, arguments: &[Argument]) -> Vec<String> {
+        self.resolve_in(arguments, &self.environment())
+    }
+
+    /// Substitutes the arguments that apply in `env`, in order.
+    pub fn resolve_in(&self, arguments: &[Argument], env: &Environment) -> Vec<String> {
+        arguments
+            .iter()
+            .flat_map(|argument| argument.values(env))
+            .map(|value| self.substitute(value))
+            .collect()
+    }
+}
@@ -0,0 +1,890 @@
+// SPDX-FileCopyrightText: 2023 Manuel Quarneti <manuelquarneti@protonmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+pub mod arguments;
+pub mod rules;
+
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::BufReader,
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail, Result};
+use serde::Deserialize;
+use tempfile::TempDir;
+
+use crate::accounts::Account;
+use crate::jobs::JobPlan;
+use crate::paths::{ASSETS_DIR, LIBRARIES_DIR, META_DIR, NATIVES_DIR};
+use crate::tasks::{Slot, TaskGraph, TaskId};
+use crate::{blocking, cache, extract, fabric, runtimes, space, Context};
+use crate::{DownloadItem, DownloadQueue, Hash, HashAlgorithm, ItemState};
+use arguments::{Arguments, LaunchContext, LEGACY_JVM};
+use rules::{Argument, Environment, Rule};
+
+#[cfg(target_os = "windows")]
+const SEPARATOR: char = ';';
+
+#[cfg(not(target_os = "windows"))]
+const SEPARATOR: char = ':';
+
+#[derive(Deserialize)]
+struct VersionManifest {
+    versions: Vec<Version>,
+}
+
+#[derive(Deserialize)]
+pub struct Version {
+    id: String,
+    url: String,
+    sha1: String,
+}
+
+fn get_manifest(ctx: &Context) -> Result<VersionManifest> {
+    let url = format!(
+        "{}/mc/game/version_manifest_v2.json",
+        ctx.endpoints().piston_meta
+    );
+
+    ctx.get_json_cached(&url, cache::SHORT_TTL)
+}
+
+pub async fn get_versions(ctx: Context) -> Result<Vec<String>> {
+    blocking::run(move || {
+        if ctx.is_offline() {
+            return get_installed_versions();
+        }
+
+        let versions = get_manifest(&ctx)?
+            .versions
+            .into_iter()
+            .map(|v| v.id)
+            .collect::<Vec<String>>();
+
+        Ok(versions)
+    })
+    .await
+}
+
+/// Returns the versions whose metadata and client are already on disk, newest first.
+pub fn get_installed_versions() -> Result<Vec<String>> {
+    let dir = META_DIR.join("versions");
+    if !dir.exists() {
+        return Ok(vec![]);
+    }
+
+    let mut versions = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .filter_map(|path| {
+            let id = path.file_stem()?.to_str()?;
+            let meta = VersionMeta::load(id).ok()?;
+            meta.get_client_path().exists().then_some(meta)
+        })
+        .collect::<Vec<_>>();
+
+    versions.sort_by(|a, b| b.release_time.cmp(&a.release_time));
+
+    Ok(versions.into_iter().map(|meta| meta.id).collect())
+}
+
+#[derive(Deserialize)]
+struct AssetIndexMeta {
+    id: String,
+    sha1: String,
+    size: u64,
+    url: String,
+}
+
+#[derive(Deserialize)]
+struct Artifact {
+    url: String,
+    path: String,
+    sha1: String,
+    size: u64,
+}
+
+#[derive(Deserialize, Default)]
+struct LibraryDownloads {
+    /// Missing for libraries that only ship natives
+    artifact: Option<Artifact>,
+    /// Native jars, by the names in `Library::natives`
+    #[serde(default)]
+    classifiers: HashMap<String, Artifact>,
+}
+
+#[derive(Deserialize, Default)]
+struct ExtractRules {
+    #[serde(default)]
+    exclude: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct Library {
+    #[serde(default)]
+    downloads: LibraryDownloads,
+    rules: Option<Vec<Rule>>,
+    /// The classifier holding the natives for each OS, before 1.19
+    natives: Option<HashMap<String, String>>,
+    #[serde(default)]
+    extract: ExtractRules,
+}
+
+impl Library {
+    /// The jar to put on the classpath, if the library applies in `env`.
+    fn get_artifact(&self, env: &Environment) -> Option<&Artifact> {
+        if !env.allows(self.rules.as_deref()) {
+            return None;
+        }
+
+        self.downloads.artifact.as_ref()
+    }
+
+    /// The jar with the natives for `env`, to extract before launching.
+    fn get_natives(&self, env: &Environment) -> Option<&Artifact> {
+        if !env.allows(self.rules.as_deref()) {
+            return None;
+        }
+
+        let classifier = self.natives.as_ref()?.get(&env.os_name)?;
+        let bits = if env.arch == "x86" { "32" } else { "64" };
+
+        self.downloads
+            .classifiers
+            .get(&classifier.replace("${arch}", bits))
+    }
+}
+
+#[derive(Deserialize)]
+struct ClientArtifact {
+    sha1: String,
+    size: u64,
+    url: String,
+}
+
+#[derive(Deserialize)]
+struct VersionDownloads {
+    client: ClientArtifact,
+}
+
+#[derive(Deserialize)]
+struct JavaVersion {
+    #[serde(rename = "majorVersion")]
+    major_version: u32,
+}
+
+#[derive(Deserialize)]
+pub struct VersionMeta {
+    id: String,
+    #[serde(rename = "assetIndex")]
+    asset_index: AssetIndexMeta,
+    libraries: Vec<Library>,
+    #[serde(rename = "mainClass")]
+    pub main_class: String,
+    pub assets: String,
+    downloads: VersionDownloads,
+    #[serde(rename = "releaseTime")]
+    release_time: String,
+    #[serde(rename = "type")]
+    version_type: String,
+    /// Since 1.13
+    arguments: Option<Arguments>,
+    /// Game arguments before 1.13
+    #[serde(rename = "minecraftArguments")]
+    minecraft_arguments: Option<String>,
+    #[serde(rename = "javaVersion")]
+    java_version: Option<JavaVersion>,
+}
+
+impl VersionMeta {
+    pub fn load(id: &str) -> Result<Self> {
+        let path = META_DIR.join("versions").join(format!("{}.json", id));
+        let file = File::open(path)?;
+        let reader = BufReader::new(file);
+        let version_meta = serde_json::from_reader(reader)?;
+
+        Ok(version_meta)
+    }
+
+    /// The Java major version the game runs on. Versions too old to say need Java 8.
+    pub fn get_java_major(&self) -> u32 {
+        self.java_version
+            .as_ref()
+            .map_or(8, |java_version| java_version.major_version)
+    }
+
+    fn get_client_path(&self) -> PathBuf {
+        LIBRARIES_DIR
+            .join("com")
+            .join("mojang")
+            .join("minecraft")
+            .join(self.id.as_str())
+            .join(format!("minecraft-{}-client.jar", self.id))
+    }
+
+    fn get_asset_index_path(&self) -> PathBuf {
+        ASSETS_DIR
+            .join("indexes")
+            .join(format!("{}.json", self.asset_index.id))
+    }
+
+    /// Extracts the natives into a new directory, removed once the returned handle is dropped.
+    pub fn extract_natives(&self) -> Result<TempDir> {
+        let dir = tempfile::Builder::new()
+            .prefix(&format!("{}-", self.id))
+            .tempdir_in(&*NATIVES_DIR)?;
+
+        let env = Environment::default();
+
+        for library in &self.libraries {
+            if let Some(natives) = library.get_natives(&env) {
+                let path = LIBRARIES_DIR.join(&natives.path);
+                extract::extract_zip_into(&path, dir.path(), &library.extract.exclude)?;
+            }
+        }
+
+        Ok(dir)
+    }
+
+    /// Fills in the placeholders that depend on this version and `account`.
+    ///
+    /// Versions before 1.7.2 read the assets by name, so they're laid out for them here.
+    pub fn get_launch_context(
+        &self,
+        account: &Account,
+        game_directory: PathBuf,
+        natives_directory: PathBuf,
+    ) -> Result<LaunchContext> {
+        let asset_index = AssetIndex::load(&self.get_asset_index_path())?;
+        let game_assets =
+            match asset_index.get_game_assets(&self.assets, &ASSETS_DIR, &game_directory) {
+                Some(dir) => {
+                    asset_index.copy_objects(&dir)?;
+                    dir
+                }
+                None => ASSETS_DIR.clone(),
+            };
+
+        Ok(LaunchContext {
+            player_name: account.mc_username.clone(),
+            uuid: account.mc_id.clone(),
+            access_token: account.mc_access_token.clone(),
+            xuid: account.get_xuid().unwrap_or_default(),
+            user_type: account.get_user_type().to_string(),
+            version_name: self.id.clone(),
+            version_type: self.version_type.clone(),
+            game_directory,
+            assets_root: ASSETS_DIR.clone(),
+            game_assets,
+            assets_index_name: self.assets.clone(),
+            natives_directory,
+            classpath: self.get_classpath()?,
+            resolution: None,
+        })
+    }
+
+    /// The JVM arguments of this version, or the ones the launcher used before versions listed them.
+    fn get_jvm_argument_list(&self) -> &[Argument] {
+        match &self.arguments {
+            Some(arguments) if !arguments.jvm.is_empty() => &arguments.jvm,
+            _ => &LEGACY_JVM,
+        }
+    }
+
+    pub fn get_jvm_arguments(&self, launch: &LaunchContext) -> Vec<String> {
+        launch.resolve(self.get_jvm_argument_list())
+    }
+
+    pub fn get_game_arguments(&self, launch: &LaunchContext) -> Vec<String> {
+        if let Some(arguments) = &self.arguments {
+            return launch.resolve(&arguments.game);
+        }
+
+        self.minecraft_arguments
+            .as_deref()
+            .unwrap_or_default()
+            .split_whitespace()
+            .map(|argument| launch.substitute(argument))
+            .collect()
+    }
+
+    pub fn get_classpath(&self) -> Result<String> {
+        let env = Environment::default();
+        let mut paths = vec![self.get_client_path()];
+
+        for library in &self.libraries {
+            if let Some(artifact) = library.get_artifact(&env) {
+                let path = LIBRARIES_DIR.join(&artifact.path);
+
+                paths.push(path);
+            }
+        }
+
+        let classpath = paths
+            .into_iter()
+            .map(|p| p.to_str().unwrap().to_string())
+            .collect::<Vec<String>>()
+            .join(&SEPARATOR.to_string());
+
+        Ok(classpath)
+    }
+}
+
+#[derive(Deserialize)]
+struct Object {
+    hash: String,
+    size: u64,
+}
+
+#[derive(Deserialize)]
+struct AssetIndex {
+    objects: HashMap<String, Object>,
+    /// Up to 1.7.2, the game reads the assets by name from `assets/virtual/<id>`
+    #[serde(default, rename = "virtual")]
+    is_virtual: bool,
+    /// Before 1.6, from the `resources` directory of the game instead
+    #[serde(default)]
+    map_to_resources: bool,
+}
+
+impl AssetIndex {
+    fn load(path: &Path) -> Result<Self> {
+        let reader = BufReader::new(File::open(path)?);
+
+        Ok(serde_json::from_reader(reader)?)
+    }
+
+    /// Where the game looks for the assets by name, if it doesn't use `objects`.
+    fn get_game_assets(
+        &self,
+        id: &str,
+        assets_root: &Path,
+        game_directory: &Path,
+    ) -> Option<PathBuf> {
+        if self.map_to_resources {
+            Some(game_directory.join("resources"))
+        } else if self.is_virtual {
+            Some(assets_root.join("virtual").join(id))
+        } else {
+            None
+        }
+    }
+
+    /// Puts a copy of every object under its name in `dir`.
+    ///
+    /// Objects shared with the other versions are hard linked, but the resources of an instance
+    /// are copied, as the game writes to them.
+    fn copy_objects(&self, dir: &Path) -> Result<()> {
+        self.copy_objects_from(&ASSETS_DIR.join("objects"), dir)
+    }
+
+    fn copy_objects_from(&self, objects_dir: &Path, dir: &Path) -> Result<()> {
+        for (name, object) in &self.objects {
+            // the names come from the index, so keep them inside `dir`
+            let is_relative = Path::new(name)
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)));
+            if !is_relative {
+                bail!("Invalid asset name: {}", name);
+            }
+
+            let dest = dir.join(name);
+            if fs::metadata(&dest).is_ok_and(|metadata| metadata.len() == object.size) {
+                continue;
+            }
+
+            if let Some(parent) = dest.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            if dest.exists() {
+                fs::remove_file(&dest)?;
+            }
+
+            let hash = Hash {
+                hash: object.hash.clone(),
+                function: HashAlgorithm::Sha1,
+            };
+            let src = objects_dir.join(hash.get_path());
+
+            if self.map_to_resources || fs::hard_link(&src, &dest).is_err() {
+                fs::copy(&src, &dest)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RepairReport {
+    pub checked: usize,
+    pub missing: Vec<PathBuf>,
+    pub corrupted: Vec<PathBuf>,
+}
+
+impl RepairReport {
+    pub fn broken(&self) -> usize {
+        self.missing.len() + self.corrupted.len()
+    }
+}
+
+fn fetch_version_meta(ctx: &Context, id: &str) -> Result<VersionMeta> {
+    // the manifest may not be cached, but there's nothing to check the local copy against anyway
+    if ctx.is_offline() {
+        return VersionMeta::load(id);
+    }
+
+    let version = get_manifest(ctx)?
+        .versions
+        .into_iter()
+        .find(|v| v.id == id)
+        .ok_or_else(|| anyhow!("Version {} not found", id))?;
+
+    // download version meta
+    DownloadItem {
+        url: version.url,
+        mirrors: vec![],
+        path: META_DIR.join("versions").join(format!("{}.json", id)),
+        hash: Some(Hash {
+            hash: version.sha1,
+            function: HashAlgorithm::Sha1,
+        }),
+        size: None,
+        extract: None,
+    }
+    .download_json(ctx)
+}
+
+fn fetch_asset_index(ctx: &Context, version_meta: &VersionMeta) -> Result<AssetIndex> {
+    let asset_index = &version_meta.asset_index;
+
+    DownloadItem {
+        url: asset_index.url.clone(),
+        mirrors: vec![],
+        path: version_meta.get_asset_index_path(),
+        hash: Some(Hash {
+            hash: asset_index.sha1.clone(),
+            function: HashAlgorithm::Sha1,
+        }),
+        size: Some(asset_index.size),
+        extract: None,
+    }
+    .download_json(ctx)
+}
+
+fn get_asset_items(ctx: &Context, asset_index: &AssetIndex) -> Vec<DownloadItem> {
+    asset_index
+        .objects
+        .values()
+        .map(|object| {
+            let hash = Hash {
+                hash: object.hash.clone(),
+                function: HashAlgorithm::Sha1,
+            };
+
+            DownloadItem {
+                url: format!("{}/{}", ctx.endpoints().resources, hash.get_path()),
+                mirrors: vec![],
+                path: ASSETS_DIR.join("objects").join(hash.get_path()),
+                hash: Some(hash),
+                size: Some(object.size),
+                extract: None,
+            }
+        })
+        .collect()
+}
+
+/// The client jar and the libraries for this platform.
+fn get_library_items(version_meta: &VersionMeta) -> Vec<DownloadItem> {
+    let client = &version_meta.downloads.client;
+
+    let mut download_items = vec![DownloadItem {
+        url: client.url.clone(),
+        mirrors: vec![],
+        path: version_meta.get_client_path(),
+        hash: Some(Hash {
+            hash: client.sha1.clone(),
+            function: HashAlgorithm::Sha1,
+        }),
+        size: Some(client.size),
+        extract: None,
+    }];
+
+    let env = Environment::default();
+
+    for library in &version_meta.libraries {
+        let artifacts = [library.get_artifact(&env), library.get_natives(&env)];
+
+        for artifact in artifacts.into_iter().flatten() {
+            download_items.push(DownloadItem {
+                url: artifact.url.clone(),
+                mirrors: vec![],
+                path: LIBRARIES_DIR.join(&artifact.path),
+                hash: Some(Hash {
+                    hash: artifact.sha1.clone(),
+                    function: HashAlgorithm::Sha1,
+                }),
+                size: Some(artifact.size),
+                extract: None,
+            });
+        }
+    }
+
+    download_items
+}
+
+fn get_download_items(
+    ctx: &Context,
+    id: &str,
+    java_major: Option<u32>,
+    java_release: Option<&str>,
+) -> Result<Vec<DownloadItem>> {
+    let version_meta = fetch_version_meta(ctx, id)?;
+    let java_major = java_major.unwrap_or_else(|| version_meta.get_java_major());
+
+    let mut download_items = get_library_items(&version_meta);
+    download_items.extend(runtimes::install(ctx, java_major, java_release)?);
+
+    let asset_index = fetch_asset_index(ctx, &version_meta)?;
+    download_items.extend(get_asset_items(ctx, &asset_index));
+
+    Ok(download_items)
+}
+
+/// Every library and asset `id` uses, read from the metadata on disk.
+pub(crate) fn get_installed_files(id: &str) -> Result<Vec<PathBuf>> {
+    let version_meta = VersionMeta::load(id)?;
+
+    let index_path = version_meta.get_asset_index_path();
+    let asset_index =
+        serde_json::from_reader::<_, AssetIndex>(BufReader::new(File::open(&index_path)?))?;
+
+    let mut files = get_library_items(&version_meta)
+        .into_iter()
+        .map(|item| item.path)
+        .collect::<Vec<_>>();
+
+    files.extend(asset_index.objects.into_values().map(|object| {
+        let hash = Hash {
+            hash: object.hash,
+            function: HashAlgorithm::Sha1,
+        };
+
+        ASSETS_DIR.join("objects").join(hash.get_path())
+    }));
+    files.push(index_path);
+
+    Ok(files)
+}
+
+/// The tasks installing a version, for other tasks to depend on.
+pub struct VersionTasks {
+    pub meta: TaskId,
+    pub asset_index: TaskId,
+    pub check_space: TaskId,
+    pub assets: TaskId,
+    pub libraries: TaskId,
+    pub runtime: TaskId,
+    /// Filled in once `meta` is done
+    pub version_meta: Slot<VersionMeta>,
+}
+
+/// Adds the tasks installing `version` to `graph`, to run after `deps`.
+///
+/// The version can be the output of an earlier task, like the Minecraft version of a modpack.
+/// `java_major` and `java_release` are the Java override of the instance, as in [`repair_version`].
+/// `extra_items` are downloaded by other tasks, but count towards the free space check too.
+pub fn add_tasks(
+    graph: &mut TaskGraph,
+    version: Slot<String>,
+    java_major: Option<u32>,
+    java_release: Option<String>,
+    extra_items: Option<Slot<Vec<DownloadItem>>>,
+    deps: &[TaskId],
+) -> VersionTasks {
+    let version_meta = Slot::new();
+    let asset_index = Slot::new();
+    let runtime_items = Slot::<Vec<DownloadItem>>::new();
+
+    let meta = graph.add("Fetch version metadata", deps, {
+        let version_meta = version_meta.clone();
+
+        move |task| {
+            version_meta.set(fetch_version_meta(task.ctx(), &version.get()?)?);
+            Ok(())
+        }
+    });
+
+    let fetch_index = graph.add("Fetch asset index", &[meta], {
+        let version_meta = version_meta.clone();
+        let asset_index = asset_index.clone();
+
+        move |task| {
+            asset_index.set(fetch_asset_index(task.ctx(), &*version_meta.get()?)?);
+            Ok(())
+        }
+    });
+
+    // everything's size is known by now, so check it all fits before anything is downloaded
+    let check_space = graph.add("Check free space", &[fetch_index], {
+        let version_meta = version_meta.clone();
+        let asset_index = asset_index.clone();
+        let runtime_items = runtime_items.clone();
+
+        move |task| {
+            let version_meta = version_meta.get()?;
+            let java_major = java_major.unwrap_or_else(|| version_meta.get_java_major());
+            let runtime = runtimes::install(task.ctx(), java_major, java_release.as_deref())?;
+
+            let mut items = get_library_items(&version_meta);
+            items.extend(get_asset_items(task.ctx(), &*asset_index.get()?));
+            items.extend(runtime.iter().cloned());
+            if let Some(extra_items) = &extra_items {
+                items.extend(extra_items.get()?.iter().cloned());
+            }
+            space::check(&items)?;
+
+            runtime_items.set(runtime);
+            Ok(())
+        }
+    });
+
+    let assets = graph.add("Download assets", &[check_space], move |task| {
+        task.download(get_asset_items(task.ctx(), &*asset_index.get()?))
+    });
+
+    let libraries = graph.add("Download libraries", &[check_space], {
+        let version_meta = version_meta.clone();
+
+        move |task| task.download(get_library_items(&*version_meta.get()?))
+    });
+
+    let runtime = graph.add("Install Java", &[check_space], move |task| {
+        task.download(runtime_items.get()?.to_vec())
+    });
+
+    VersionTasks {
+        meta,
+        asset_index: fetch_index,
+        check_space,
+        assets,
+        libraries,
+        runtime,
+        version_meta,
+    }
+}
+
+/// A graph installing `id`, with Fabric on top if `fabric_version` is set, and the Java runtime
+/// the instance asks for.
+pub fn download_version(
+    id: &str,
+    fabric_version: Option<&str>,
+    java_major: Option<u32>,
+    java_release: Option<&str>,
+) -> TaskGraph {
+    let mut graph = TaskGraph::new();
+    let version = Slot::filled(id.to_string());
+    let java_release = java_release.map(str::to_string);
+    let tasks = add_tasks(
+        &mut graph,
+        version.clone(),
+        java_major,
+        java_release.clone(),
+        None,
+        &[],
+    );
+
+    if let Some(fabric_version) = fabric_version {
+        let fabric_version = Slot::filled(Some(fabric_version.to_string()));
+        fabric::add_tasks(&mut graph, version, fabric_version, &[tasks.meta]);
+    }
+
+    graph.with_plan(JobPlan::Version {
+        id: id.to_string(),
+        fabric: fabric_version.map(str::to_string),
+        java_major,
+        java_release,
+    })
+}
+
+/// Every file `download_version` would produce, Fabric libraries included.
+pub(crate) fn get_version_items(
+    ctx: &Context,
+    id: &str,
+    fabric_version: Option<&str>,
+    java_major: Option<u32>,
+    java_release: Option<&str>,
+) -> Result<Vec<DownloadItem>> {
+    let mut items = get_download_items(ctx, id, java_major, java_release)?;
+
+    if let Some(fabric_version) = fabric_version {
+        items.extend(fabric::get_download_items(ctx, id, fabric_version)?);
+    }
+
+    Ok(items)
+}
+
+/// Rehashes every file `download_version` would produce and queues the missing or corrupted ones.
+///
+/// `java_major` replaces the Java version the game asks for, to repair a runtime picked by hand,
+/// and `java_release` is the release the instance is pinned to.
+pub fn repair_version(
+    ctx: &Context,
+    id: &str,
+    fabric_version: Option<&str>,
+    java_major: Option<u32>,
+    java_release: Option<&str>,
+) -> Result<(DownloadQueue, RepairReport)> {
+    let items = get_version_items(ctx, id, fabric_version, java_major, java_release)?;
+
+    repair_items(items)
+}
+
+/// Rehashes `items` and queues the missing or corrupted ones.
+pub(crate) fn repair_items(items: Vec<DownloadItem>) -> Result<(DownloadQueue, RepairReport)> {
+    let mut report = RepairReport::default();
+    let mut download_items = vec![];
+
+    for item in items {
+        report.checked += 1;
+
+        match item.check()? {
+            ItemState::Valid => {}
+            ItemState::Missing => {
+                report.missing.push(item.path.clone());
+                download_items.push(item);
+            }
+            ItemState::Corrupted => {
+                println!("corrupted file: {}", item.path.display());
+
+                fs::remove_file(&item.path)?;
+                report.corrupted.push(item.path.clone());
+                download_items.push(item);
+            }
+        }
+    }
+
+    space::check(&download_items)?;
+
+    Ok((DownloadQueue::new(download_items), report))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::cache::MetaCache;
+    use crate::endpoints::Endpoints;
+    use crate::transport::MemoryTransport;
+
+    const LEGACY_INDEX: &str = r#"{
+        "virtual": true,
+        "objects": {
+            "sounds/step/grass1.ogg": { "hash": "a9993e364706816aba3e25717850c26c9cd0d89d", "size": 3 },
+            "lang/en_US.lang": { "hash": "a94a8fe5ccb19ba61c4c0823d2da19f27a65bd18", "size": 4 }
+        }
+    }"#;
+
+    fn write_object(objects_dir: &Path, hash: &str, contents: &str) {
+        let path = objects_dir.join(&hash[..2]).join(hash);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    #[test]
+    fn game_assets_follow_the_index() {
+        let index = serde_json::from_str::<AssetIndex>(LEGACY_INDEX).unwrap();
+        assert_eq!(
+            index.get_game_assets("legacy", Path::new("/assets"), Path::new("/game")),
+            Some(Path::new("/assets").join("virtual").join("legacy"))
+        );
+
+        let index =
+            serde_json::from_str::<AssetIndex>(r#"{ "map_to_resources": true, "objects": {} }"#)
+                .unwrap();
+        assert_eq!(
+            index.get_game_assets("pre-1.6", Path::new("/assets"), Path::new("/game")),
+            Some(Path::new("/game").join("resources"))
+        );
+
+        let index = serde_json::from_str::<AssetIndex>(r#"{ "objects": {} }"#).unwrap();
+        assert_eq!(
+            index.get_game_assets("17", Path::new("/assets"), Path::new("/game")),
+            None
+        );
+    }
+
+    #[test]
+    fn copies_objects_by_name() {
+        let dir = tempfile::tempdir().unwrap();
+        let objects_dir = dir.path().join("objects");
+        let virtual_dir = dir.path().join("virtual");
+        write_object(
+            &objects_dir,
+            "a9993e364706816aba3e25717850c26c9cd0d89d",
+            "abc",
+        );
+        write_object(
+            &objects_dir,
+            "a94a8fe5ccb19ba61c4c0823d2da19f27a65bd18",
+            "test",
+        );
+
+        let index = serde_json::from_str::<AssetIndex>(LEGACY_INDEX).unwrap();
+        index.copy_objects_from(&objects_dir, &virtual_dir).unwrap();
+        // already in place the second time
+        index.copy_objects_from(&objects_dir, &virtual_dir).unwrap();
+
+        let read = |name: &str| fs::read_to_string(virtual_dir.join(name)).unwrap();
+        assert_eq!(read("sounds/step/grass1.ogg"), "abc");
+        assert_eq!(read("lang/en_US.lang"), "test");
+    }
+
+    #[test]
+    fn rejects_asset_names_outside_the_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let index = serde_json::from_str::<AssetIndex>(
+            r#"{ "virtual": true, "objects": { "../escape": { "hash": "aa", "size": 1 } } }"#,
+        )
+        .unwrap();
+
+        assert!(index
+            .copy_objects_from(&dir.path().join("objects"), &dir.path().join("virtual"))
+            .is_err());
+    }
+
+    #[test]
+    fn manifest_is_fetched_once_and_cached() {
+        let dir = tempfile::tempdir().unwrap();
+        let transport = Arc::new(MemoryTransport::new());
+        transport.add_json(
+            "https://piston-meta.mojang.com/mc/game/version_manifest_v2.json",
+            &json!({
+                "versions": [
+                    { "id": "1.20.4", "url": "https://piston-meta.mojang.com/1.20.4.json", "sha1": "a" },
+                    { "id": "1.20.3", "url": "https://piston-meta.mojang.com/1.20.3.json", "sha1": "b" },
+                ],
+            }),
+        );
+        let ctx = Context::with_transport(transport.clone(), Endpoints::default())
+            .with_cache(MetaCache::new(dir.path()));
+
+        let ids = |manifest: VersionManifest| {
+            manifest
+                .versions
+                .into_iter()
+                .map(|version| version.id)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(ids(get_manifest(&ctx).unwrap()), ["1.20.4", "1.20.3"]);
+        assert_eq!(ids(get_manifest(&ctx).unwrap()), ["1.20.4", "1.20.3"]);
+        assert_eq!(transport.requests().len(), 1);
+    }
+}
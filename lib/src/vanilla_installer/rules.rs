@@ -0,0 +1,690 @@
+// SPDX-FileCopyrightText: 2023 Manuel Quarneti <manuelquarneti@protonmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{collections::HashMap, env, fs, process};
+
+use once_cell::sync::Lazy;
+use regex_lite::Regex;
+use serde::Deserialize;
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Allow,
+    Disallow,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct OsRule {
+    pub name: Option<String>,
+    /// A regex matched against the OS version
+    pub version: Option<String>,
+    pub arch: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Rule {
+    pub action: Action,
+    pub os: Option<OsRule>,
+    pub features: Option<HashMap<String, bool>>,
+}
+
+/// What rules are matched against: the platform the game runs on and the launcher features.
+#[derive(Debug, Clone)]
+pub struct Environment {
+    /// `windows`, `osx` or `linux`, as Mojang names them
+    pub os_name: String,
+    pub os_version: String,
+    /// As Java reports it, `x86` being 32 bit
+    pub arch: String,
+    pub features: HashMap<String, bool>,
+}
+
+/// The version of the OS as Java would report it in `os.version`.
+fn get_os_version() -> String {
+    let version = if cfg!(target_os = "linux") {
+        fs::read_to_string("/proc/sys/kernel/osrelease").ok()
+    } else if cfg!(target_os = "macos") {
+        process::Command::new("sw_vers")
+            .arg("-productVersion")
+            .output()
+            .ok()
+            .map(|output| String::from_utf8_lossy(&output.stdout).to_string())
+    } else if cfg!(target_os = "windows") {
+        // "Microsoft Windows [Version 10.0.19045.3570]", Java only keeps major and minor
+        process::Command::new("cmd")
+            .args(["/c", "ver"])
+            .output()
+            .ok()
+            .and_then(|output| {
+                let output = String::from_utf8_lossy(&output.stdout).to_string();
+                let version = output
+                    .split("Version ")
+                    .nth(1)?
+                    .trim_end_matches([']', '\r', '\n']);
+
+                Some(version.split('.').take(2).collect::<Vec<_>>().join("."))
+            })
+    } else {
+        None
+    };
+
+    version.unwrap_or_default().trim().to_string()
+}
+
+static OS_VERSION: Lazy<String> = Lazy::new(get_os_version);
+
+impl Default for Environment {
+    /// The platform the launcher runs on, with every feature off.
+    fn default() -> Self {
+        let os_name = match env::consts::OS {
+            "macos" => "osx",
+            os => os,
+        };
+
+        let arch = match env::consts::ARCH {
+            "x86_64" => "x86_64",
+            "x86" => "x86",
+            "aarch64" => "arm64",
+            arch => arch,
+        };
+
+        Self {
+            os_name: os_name.to_string(),
+            os_version: OS_VERSION.clone(),
+            arch: arch.to_string(),
+            features: HashMap::new(),
+        }
+    }
+}
+
+impl Environment {
+    pub fn with_feature(mut self, feature: &str, enabled: bool) -> Self {
+        self.features.insert(feature.to_string(), enabled);
+        self
+    }
+
+    fn matches_os(&self, os: &OsRule) -> bool {
+        if os.name.as_ref().is_some_and(|name| *name != self.os_name) {
+            return false;
+        }
+
+        if os.arch.as_ref().is_some_and(|arch| *arch != self.arch) {
+            return false;
+        }
+
+        if let Some(version) = &os.version {
+            // a pattern we can't parse can't match either
+            let matches = Regex::new(version).is_ok_and(|regex| regex.is_match(&self.os_version));
+
+            if !matches {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    fn matches(&self, rule: &Rule) -> bool {
+        if rule.os.as_ref().is_some_and(|os| !self.matches_os(os)) {
+            return false;
+        }
+
+        // features the launcher doesn't know about are off
+        if let Some(features) = &rule.features {
+            for (feature, expected) in features {
+                if self.features.get(feature).copied().unwrap_or(false) != *expected {
+                    return false;
+                }
+            }
+        }
+
+        true
+    }
+
+    /// Whether something with these rules applies here.
+    ///
+    /// Without rules, everything is allowed. Otherwise it's disallowed unless a rule matches,
+    /// and the last matching rule decides.
+    pub fn allows(&self, rules: Option<&[Rule]>) -> bool {
+        let Some(rules) = rules else {
+            return true;
+        };
+
+        rules
+            .iter()
+            .rev()
+            .find(|rule| self.matches(rule))
+            .is_some_and(|rule| rule.action == Action::Allow)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum ArgumentValue {
+    Single(String),
+    Many(Vec<String>),
+}
+
+/// An entry of `arguments.game` or `arguments.jvm`.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum Argument {
+    Plain(String),
+    Conditional {
+        rules: Vec<Rule>,
+        value: ArgumentValue,
+    },
+}
+
+impl Argument {
+    /// The values this argument adds in `env`, still with their placeholders.
+    pub fn values(&self, env: &Environment) -> Vec<&str> {
+        match self {
+            Argument::Plain(value) => vec![value],
+            Argument::Conditional { rules, value } => {
+                if !env.allows(Some(rules)) {
+                    return vec![];
+                }
+
+                match value {
+                    ArgumentValue::Single(value) => vec![value],
+                    ArgumentValue::Many(values) => values.iter().map(String::as_str).collect(),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::*;
+    use crate::vanilla_installer::arguments::LaunchContext;
+    use crate::vanilla_installer::VersionMeta;
+
+    fn env(os_name: &str, os_version: &str, arch: &str) -> Environment {
+        Environment {
+            os_name: os_name.to_string(),
+            os_version: os_version.to_string(),
+            arch: arch.to_string(),
+            features: HashMap::new(),
+        }
+    }
+
+    fn linux() -> Environment {
+        env("linux", "6.5.0", "x86_64")
+    }
+
+    fn osx() -> Environment {
+        env("osx", "14.1", "x86_64")
+    }
+
+    fn windows_10() -> Environment {
+        env("windows", "10.0", "x86_64")
+    }
+
+    fn windows_10_x86() -> Environment {
+        env("windows", "10.0", "x86")
+    }
+
+    fn windows_7() -> Environment {
+        env("windows", "6.1", "x86_64")
+    }
+
+    fn rules(json: &str) -> Vec<Rule> {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn load(id: &str) -> VersionMeta {
+        let json = match id {
+            "1.2.5" => include_str!("../../tests/fixtures/versions/1.2.5.json"),
+            "1.8.9" => include_str!("../../tests/fixtures/versions/1.8.9.json"),
+            "1.12.2" => include_str!("../../tests/fixtures/versions/1.12.2.json"),
+            "1.16.5" => include_str!("../../tests/fixtures/versions/1.16.5.json"),
+            "1.19" => include_str!("../../tests/fixtures/versions/1.19.json"),
+            "24w14a" => include_str!("../../tests/fixtures/versions/24w14a.json"),
+            _ => unreachable!(),
+        };
+
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn file_name(path: &str) -> String {
+        Path::new(path)
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .to_string()
+    }
+
+    /// The jars put on the classpath in `env`, by file name.
+    fn artifacts(meta: &VersionMeta, env: &Environment) -> Vec<String> {
+        meta.libraries
+            .iter()
+            .filter_map(|library| library.get_artifact(env))
+            .map(|artifact| file_name(&artifact.path))
+            .collect()
+    }
+
+    /// The native jars extracted in `env`, by file name.
+    fn natives(meta: &VersionMeta, env: &Environment) -> Vec<String> {
+        meta.libraries
+            .iter()
+            .filter_map(|library| library.get_natives(env))
+            .map(|artifact| file_name(&artifact.path))
+            .collect()
+    }
+
+    fn launch(meta: &VersionMeta) -> LaunchContext {
+        LaunchContext {
+            player_name: "Steve".to_string(),
+            uuid: "uuid".to_string(),
+            access_token: "token".to_string(),
+            xuid: "xuid".to_string(),
+            user_type: "msa".to_string(),
+            version_name: meta.id.clone(),
+            version_type: meta.version_type.clone(),
+            game_directory: PathBuf::from("/game"),
+            assets_root: PathBuf::from("/assets"),
+            game_assets: PathBuf::from("/game").join("resources"),
+            assets_index_name: meta.assets.clone(),
+            natives_directory: PathBuf::from("/natives"),
+            classpath: "CP".to_string(),
+            resolution: None,
+        }
+    }
+
+    fn jvm_arguments(meta: &VersionMeta, env: &Environment) -> Vec<String> {
+        launch(meta).resolve_in(meta.get_jvm_argument_list(), env)
+    }
+
+    fn game_arguments(
+        meta: &VersionMeta,
+        launch: &LaunchContext,
+        env: &Environment,
+    ) -> Vec<String> {
+        match &meta.arguments {
+            Some(arguments) => launch.resolve_in(&arguments.game, env),
+            None => meta.get_game_arguments(launch),
+        }
+    }
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    fn legacy_jvm_tail() -> Vec<String> {
+        strings(&[
+            "-Djava.library.path=/natives",
+            concat!("-Dminecraft.launcher.brand=", env!("CARGO_PKG_NAME")),
+            concat!("-Dminecraft.launcher.version=", env!("CARGO_PKG_VERSION")),
+            "-cp",
+            "CP",
+        ])
+    }
+
+    #[test]
+    fn no_rules_allow_everything() {
+        assert!(linux().allows(None));
+        assert!(!linux().allows(Some(&[])));
+        assert!(linux().allows(Some(&rules(r#"[{ "action": "allow" }]"#))));
+        assert!(!linux().allows(Some(&rules(r#"[{ "action": "disallow" }]"#))));
+    }
+
+    #[test]
+    fn last_matching_rule_decides() {
+        let not_osx =
+            rules(r#"[{ "action": "allow" }, { "action": "disallow", "os": { "name": "osx" } }]"#);
+
+        assert!(linux().allows(Some(&not_osx)));
+        assert!(windows_10().allows(Some(&not_osx)));
+        assert!(!osx().allows(Some(&not_osx)));
+
+        let only_osx = rules(r#"[{ "action": "allow", "os": { "name": "osx" } }]"#);
+
+        assert!(!linux().allows(Some(&only_osx)));
+        assert!(osx().allows(Some(&only_osx)));
+    }
+
+    #[test]
+    fn os_version_is_a_regex() {
+        let windows_10_rule =
+            rules(r#"[{ "action": "allow", "os": { "name": "windows", "version": "^10\\." } }]"#);
+
+        assert!(windows_10().allows(Some(&windows_10_rule)));
+        assert!(!windows_7().allows(Some(&windows_10_rule)));
+        assert!(!linux().allows(Some(&windows_10_rule)));
+
+        let broken = rules(r#"[{ "action": "allow", "os": { "version": "(" } }]"#);
+        assert!(!windows_10().allows(Some(&broken)));
+    }
+
+    #[test]
+    fn os_arch_matches_alone() {
+        let x86 = rules(r#"[{ "action": "allow", "os": { "arch": "x86" } }]"#);
+
+        assert!(windows_10_x86().allows(Some(&x86)));
+        assert!(env("linux", "6.5.0", "x86").allows(Some(&x86)));
+        assert!(!windows_10().allows(Some(&x86)));
+    }
+
+    #[test]
+    fn features_must_all_match() {
+        let resolution =
+            rules(r#"[{ "action": "allow", "features": { "has_custom_resolution": true } }]"#);
+        let not_demo = rules(r#"[{ "action": "allow", "features": { "is_demo_user": false } }]"#);
+
+        // unknown features are off
+        assert!(!linux().allows(Some(&resolution)));
+        assert!(linux().allows(Some(&not_demo)));
+
+        let env = linux()
+            .with_feature("has_custom_resolution", true)
+            .with_feature("is_demo_user", true);
+        assert!(env.allows(Some(&resolution)));
+        assert!(!env.allows(Some(&not_demo)));
+    }
+
+    #[test]
+    fn legacy_libraries() {
+        let meta = load("1.2.5");
+
+        assert_eq!(
+            artifacts(&meta, &linux()),
+            strings(&[
+                "launchwrapper-1.5.jar",
+                "jinput-2.0.5.jar",
+                "jutils-1.0.0.jar",
+                "lwjgl-2.9.0.jar",
+                "lwjgl_util-2.9.0.jar",
+            ])
+        );
+        assert_eq!(
+            natives(&meta, &linux()),
+            strings(&[
+                "lwjgl-platform-2.9.0-natives-linux.jar",
+                "jinput-platform-2.0.5-natives-linux.jar",
+            ])
+        );
+
+        assert_eq!(
+            artifacts(&meta, &osx()),
+            strings(&[
+                "launchwrapper-1.5.jar",
+                "jinput-2.0.5.jar",
+                "jutils-1.0.0.jar",
+                "lwjgl-2.9.1-nightly-20130708-debug3.jar",
+                "lwjgl_util-2.9.1-nightly-20130708-debug3.jar",
+            ])
+        );
+        assert_eq!(
+            natives(&meta, &osx()),
+            strings(&[
+                "lwjgl-platform-2.9.1-nightly-20130708-debug3-natives-osx.jar",
+                "jinput-platform-2.0.5-natives-osx.jar",
+            ])
+        );
+    }
+
+    #[test]
+    fn natives_by_arch() {
+        let meta = load("1.8.9");
+
+        assert_eq!(
+            natives(&meta, &windows_10_x86()),
+            strings(&[
+                "lwjgl-platform-2.9.4-nightly-20150209-natives-windows.jar",
+                "twitch-platform-6.5-natives-windows-32.jar",
+                "twitch-external-platform-4.5-natives-windows-32.jar",
+            ])
+        );
+        assert_eq!(
+            natives(&meta, &windows_10()),
+            strings(&[
+                "lwjgl-platform-2.9.4-nightly-20150209-natives-windows.jar",
+                "twitch-platform-6.5-natives-windows-64.jar",
+                "twitch-external-platform-4.5-natives-windows-64.jar",
+            ])
+        );
+        assert_eq!(
+            natives(&meta, &linux()),
+            strings(&["lwjgl-platform-2.9.4-nightly-20150209-natives-linux.jar"])
+        );
+        assert_eq!(
+            natives(&meta, &osx()),
+            strings(&[
+                "lwjgl-platform-2.9.2-nightly-20140822-natives-osx.jar",
+                "twitch-platform-6.5-natives-osx.jar",
+            ])
+        );
+        assert_eq!(
+            artifacts(&meta, &osx()),
+            strings(&[
+                "netty-1.6.jar",
+                "lwjgl-2.9.2-nightly-20140822.jar",
+                "twitch-6.5.jar",
+            ])
+        );
+    }
+
+    #[test]
+    fn natives_missing_for_an_os() {
+        let meta = load("1.12.2");
+
+        assert_eq!(
+            artifacts(&meta, &linux()),
+            strings(&[
+                "patchy-1.3.9.jar",
+                "text2speech-1.10.3.jar",
+                "lwjgl-2.9.4-nightly-20150209.jar",
+            ])
+        );
+        assert_eq!(
+            natives(&meta, &linux()),
+            strings(&[
+                "text2speech-1.10.3-natives-linux.jar",
+                "lwjgl-platform-2.9.4-nightly-20150209-natives-linux.jar",
+            ])
+        );
+
+        // text2speech has no natives for macOS
+        assert_eq!(
+            natives(&meta, &osx()),
+            strings(&[
+                "java-objc-bridge-1.0.0-natives-osx.jar",
+                "lwjgl-platform-2.9.2-nightly-20140822-natives-osx.jar",
+            ])
+        );
+    }
+
+    #[test]
+    fn lwjgl_3_natives_as_classifiers() {
+        let meta = load("1.16.5");
+
+        assert_eq!(
+            artifacts(&meta, &linux()),
+            strings(&["patchy-1.3.9.jar", "lwjgl-3.2.2.jar", "lwjgl-3.2.2.jar"])
+        );
+        assert_eq!(
+            natives(&meta, &linux()),
+            strings(&["lwjgl-3.2.2-natives-linux.jar"])
+        );
+        assert_eq!(
+            natives(&meta, &osx()),
+            strings(&[
+                "lwjgl-3.2.1-natives-macos.jar",
+                "java-objc-bridge-1.0.0-natives-osx.jar",
+            ])
+        );
+    }
+
+    #[test]
+    fn natives_as_libraries() {
+        for id in ["1.19", "24w14a"] {
+            let meta = load(id);
+            let lwjgl = if id == "1.19" {
+                "lwjgl-3.3.1"
+            } else {
+                "lwjgl-3.3.3"
+            };
+
+            assert!(natives(&meta, &linux()).is_empty());
+            assert_eq!(
+                artifacts(&meta, &linux())[1..],
+                strings(&[
+                    &format!("{lwjgl}.jar"),
+                    &format!("{lwjgl}-natives-linux.jar"),
+                ])
+            );
+            assert_eq!(
+                artifacts(&meta, &osx())[1..],
+                strings(&[
+                    &format!("{lwjgl}.jar"),
+                    &format!("{lwjgl}-natives-macos.jar"),
+                    &format!("{lwjgl}-natives-macos-arm64.jar"),
+                ])
+            );
+            assert_eq!(
+                artifacts(&meta, &windows_10_x86())[1..],
+                strings(&[
+                    &format!("{lwjgl}.jar"),
+                    &format!("{lwjgl}-natives-windows.jar"),
+                    &format!("{lwjgl}-natives-windows-arm64.jar"),
+                    &format!("{lwjgl}-natives-windows-x86.jar"),
+                ])
+            );
+        }
+    }
+
+    #[test]
+    fn legacy_arguments() {
+        let meta = load("1.2.5");
+        let launch = launch(&meta);
+        let game_assets = Path::new("/game").join("resources");
+
+        assert_eq!(
+            game_arguments(&meta, &launch, &linux()),
+            strings(&[
+                "Steve",
+                "token:token:uuid",
+                "--gameDir",
+                "/game",
+                "--assetsDir",
+                &game_assets.to_string_lossy(),
+            ])
+        );
+
+        assert_eq!(jvm_arguments(&meta, &linux()), legacy_jvm_tail());
+
+        let mut osx_arguments = strings(&["-XstartOnFirstThread"]);
+        osx_arguments.extend(legacy_jvm_tail());
+        assert_eq!(jvm_arguments(&meta, &osx()), osx_arguments);
+
+        let mut x86_arguments = strings(&[
+            "-XX:HeapDumpPath=MojangTricksIntelDriversForPerformance_javaw.exe_minecraft.exe.heapdump",
+            "-Xss1M",
+        ]);
+        x86_arguments.extend(legacy_jvm_tail());
+        assert_eq!(jvm_arguments(&meta, &windows_10_x86()), x86_arguments);
+    }
+
+    #[test]
+    fn minecraft_arguments() {
+        let meta = load("1.12.2");
+        let launch = launch(&meta);
+
+        assert_eq!(
+            game_arguments(&meta, &launch, &linux()),
+            strings(&[
+                "--username",
+                "Steve",
+                "--version",
+                "1.12.2",
+                "--gameDir",
+                "/game",
+                "--assetsDir",
+                "/assets",
+                "--assetIndex",
+                "1.12",
+                "--uuid",
+                "uuid",
+                "--accessToken",
+                "token",
+                "--userType",
+                "msa",
+                "--versionType",
+                "release",
+            ])
+        );
+    }
+
+    #[test]
+    fn jvm_arguments_by_platform() {
+        let meta = load("1.16.5");
+
+        assert_eq!(jvm_arguments(&meta, &linux()), legacy_jvm_tail());
+
+        let mut windows_10_arguments = strings(&[
+            "-XX:HeapDumpPath=MojangTricksIntelDriversForPerformance_javaw.exe_minecraft.exe.heapdump",
+            "-Dos.name=Windows 10",
+            "-Dos.version=10.0",
+            "-Xss1M",
+        ]);
+        windows_10_arguments.extend(legacy_jvm_tail());
+        assert_eq!(
+            jvm_arguments(&meta, &windows_10_x86()),
+            windows_10_arguments
+        );
+
+        let mut windows_7_arguments = strings(&[
+            "-XX:HeapDumpPath=MojangTricksIntelDriversForPerformance_javaw.exe_minecraft.exe.heapdump",
+        ]);
+        windows_7_arguments.extend(legacy_jvm_tail());
+        assert_eq!(jvm_arguments(&meta, &windows_7()), windows_7_arguments);
+
+        let meta = load("1.19");
+        assert_eq!(
+            jvm_arguments(&meta, &linux()),
+            strings(&[
+                "-Djava.library.path=/natives",
+                "-Djna.tmpdir=/natives",
+                "-Dorg.lwjgl.system.SharedLibraryExtractPath=/natives",
+                "-Dio.netty.native.workdir=/natives",
+                concat!("-Dminecraft.launcher.brand=", env!("CARGO_PKG_NAME")),
+                concat!("-Dminecraft.launcher.version=", env!("CARGO_PKG_VERSION")),
+                "-cp",
+                "CP",
+            ])
+        );
+    }
+
+    #[test]
+    fn game_arguments_by_feature() {
+        let meta = load("24w14a");
+        let mut launch = launch(&meta);
+
+        let arguments = game_arguments(&meta, &launch, &linux());
+        assert_eq!(arguments.len(), 18);
+        assert_eq!(arguments[16..], strings(&["--versionType", "snapshot"]));
+
+        launch.resolution = Some((854, 480));
+        let arguments = game_arguments(&meta, &launch, &launch.environment());
+        assert_eq!(
+            arguments[18..],
+            strings(&["--width", "854", "--height", "480"])
+        );
+
+        // placeholders the launcher doesn't know are passed on untouched
+        let env = linux().with_feature("has_quick_plays_support", true);
+        let arguments = game_arguments(&meta, &launch, &env);
+        assert_eq!(
+            arguments[18..],
+            strings(&["--quickPlayPath", "${quickPlayPath}"])
+        );
+
+        let env = linux().with_feature("is_demo_user", true);
+        assert!(game_arguments(&meta, &launch, &env).contains(&"--demo".to_string()));
+    }
+}
@@ -0,0 +1,289 @@
+// SPDX-FileCopyrightText: 2023 Manuel Quarneti <manuelquarneti@protonmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    fmt,
+    fs::File,
+    io::{self, BufReader, Read},
+    path::Path,
+};
+
+use digest::Digest;
+use sha1::Sha1;
+use sha2::{Sha256, Sha512};
+
+use crate::transport::HttpError;
+use crate::{Hash, HashAlgorithm};
+
+#[derive(Debug)]
+pub enum DownloadError {
+    Http(HttpError),
+    HashMismatch {
+        expected: String,
+        actual: String,
+    },
+    Io(io::Error),
+    UnsupportedArchive(String),
+    /// The archive is malformed or tries to write outside the destination
+    InvalidArchive(String),
+    Cancelled,
+}
+
+impl fmt::Display for DownloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DownloadError::Http(error) => write!(f, "http error: {}", error),
+            DownloadError::HashMismatch { expected, actual } => {
+                write!(f, "hash mismatch: expected {}, got {}", expected, actual)
+            }
+            DownloadError::Io(error) => write!(f, "io error: {}", error),
+            DownloadError::UnsupportedArchive(url) => {
+                write!(f, "unsupported archive format: {}", url)
+            }
+            DownloadError::InvalidArchive(message) => write!(f, "invalid archive: {}", message),
+            DownloadError::Cancelled => write!(f, "download cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for DownloadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DownloadError::Http(error) => Some(error),
+            DownloadError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<HttpError> for DownloadError {
+    fn from(error: HttpError) -> Self {
+        DownloadError::Http(error)
+    }
+}
+
+impl From<io::Error> for DownloadError {
+    fn from(error: io::Error) -> Self {
+        DownloadError::Io(error)
+    }
+}
+
+impl From<zip::result::ZipError> for DownloadError {
+    fn from(error: zip::result::ZipError) -> Self {
+        DownloadError::Io(error.into())
+    }
+}
+
+impl DownloadError {
+    /// Whether trying again could help.
+    ///
+    /// Client errors won't go away by asking again, except for timeouts and rate limiting.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            DownloadError::Http(HttpError::Status { code, .. }) => {
+                !(400..500).contains(code) || [408, 429].contains(code)
+            }
+            DownloadError::Http(HttpError::Transport { .. }) => true,
+            DownloadError::Http(HttpError::Offline { .. }) => false,
+            DownloadError::HashMismatch { .. } | DownloadError::Io(_) => true,
+            DownloadError::UnsupportedArchive(_)
+            | DownloadError::InvalidArchive(_)
+            | DownloadError::Cancelled => false,
+        }
+    }
+}
+
+/// Incremental hasher, fed while the file is being written.
+pub enum Hasher {
+    Sha1(Sha1),
+    Sha256(Sha256),
+    Sha512(Sha512),
+}
+
+impl Hasher {
+    pub fn new(function: &HashAlgorithm) -> Self {
+        match function {
+            HashAlgorithm::Sha1 => Hasher::Sha1(Sha1::new()),
+            HashAlgorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+            HashAlgorithm::Sha512 => Hasher::Sha512(Sha512::new()),
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Sha1(hasher) => hasher.update(data),
+            Hasher::Sha256(hasher) => hasher.update(data),
+            Hasher::Sha512(hasher) => hasher.update(data),
+        }
+    }
+
+    pub fn update_from_reader(&mut self, mut reader: impl Read) -> io::Result<()> {
+        let mut buffer = [0; 8192];
+
+        loop {
+            let count = reader.read(&mut buffer)?;
+            if count == 0 {
+                return Ok(());
+            }
+            self.update(&buffer[..count]);
+        }
+    }
+
+    /// Returns the lowercase hex digest.
+    pub fn finalize(self) -> String {
+        match self {
+            Hasher::Sha1(hasher) => hex::encode(hasher.finalize()),
+            Hasher::Sha256(hasher) => hex::encode(hasher.finalize()),
+            Hasher::Sha512(hasher) => hex::encode(hasher.finalize()),
+        }
+    }
+}
+
+pub fn hash_file(path: &Path, function: &HashAlgorithm) -> io::Result<String> {
+    let mut hasher = Hasher::new(function);
+    hasher.update_from_reader(BufReader::new(File::open(path)?))?;
+
+    Ok(hasher.finalize())
+}
+
+/// Checks a computed digest against the expected one.
+pub fn check_digest(hash: &Hash, actual: String) -> Result<(), DownloadError> {
+    if actual.eq_ignore_ascii_case(&hash.hash) {
+        Ok(())
+    } else {
+        Err(DownloadError::HashMismatch {
+            expected: hash.hash.to_owned(),
+            actual,
+        })
+    }
+}
+
+pub fn verify_file(path: &Path, hash: &Hash) -> Result<(), DownloadError> {
+    let actual = hash_file(path, &hash.function)?;
+
+    check_digest(hash, actual)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use tempfile::NamedTempFile;
+
+    use super::*;
+
+    const MESSAGE_448: &[u8] = b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq";
+
+    /// `(algorithm, input, digest)` from FIPS 180 and its examples.
+    const VECTORS: &[(HashAlgorithm, &[u8], &str)] = &[
+        (
+            HashAlgorithm::Sha1,
+            b"",
+            "da39a3ee5e6b4b0d3255bfef95601890afd80709",
+        ),
+        (
+            HashAlgorithm::Sha1,
+            b"abc",
+            "a9993e364706816aba3e25717850c26c9cd0d89d",
+        ),
+        (
+            HashAlgorithm::Sha1,
+            MESSAGE_448,
+            "84983e441c3bd26ebaae4aa1f95129e5e54670f1",
+        ),
+        (
+            HashAlgorithm::Sha256,
+            b"",
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+        ),
+        (
+            HashAlgorithm::Sha256,
+            b"abc",
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+        ),
+        (
+            HashAlgorithm::Sha256,
+            MESSAGE_448,
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1",
+        ),
+        (
+            HashAlgorithm::Sha512,
+            b"",
+            "cf83e1357eefb8bdf1542850d66d8007d620e4050b5715dc83f4a921d36ce9ce47d0d13c5d85f2b0ff8318d2877eec2f63b931bd47417a81a538327af927da3e",
+        ),
+        (
+            HashAlgorithm::Sha512,
+            b"abc",
+            "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f",
+        ),
+        (
+            HashAlgorithm::Sha512,
+            MESSAGE_448,
+            "204a8fc6dda82f0a0ced7beb8e08a41657c16ef468b228a8279be331a703c33596fd15c13b1b07f9aa1d3bea57789ca031ad85c7a71dd70354ec631238ca3445",
+        ),
+    ];
+
+    #[test]
+    fn hasher_matches_vectors() {
+        for (function, input, digest) in VECTORS {
+            let mut hasher = Hasher::new(function);
+            hasher.update(input);
+            assert_eq!(hasher.finalize(), *digest, "{:?} of {:?}", function, input);
+        }
+    }
+
+    #[test]
+    fn hasher_matches_vectors_in_chunks() {
+        for (function, input, digest) in VECTORS {
+            let mut hasher = Hasher::new(function);
+            for chunk in input.chunks(7) {
+                hasher.update(chunk);
+            }
+            assert_eq!(hasher.finalize(), *digest, "{:?} of {:?}", function, input);
+        }
+    }
+
+    #[test]
+    fn hash_file_matches_vectors() {
+        for (function, input, digest) in VECTORS {
+            let mut file = NamedTempFile::new().unwrap();
+            file.write_all(input).unwrap();
+
+            assert_eq!(hash_file(file.path(), function).unwrap(), *digest);
+        }
+    }
+
+    #[test]
+    fn check_digest_ignores_case() {
+        let hash = Hash {
+            hash: "A9993E364706816ABA3E25717850C26C9CD0D89D".to_string(),
+            function: HashAlgorithm::Sha1,
+        };
+
+        assert!(check_digest(
+            &hash,
+            "a9993e364706816aba3e25717850c26c9cd0d89d".to_string()
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn check_digest_reports_mismatch() {
+        let hash = Hash {
+            hash: "a9993e364706816aba3e25717850c26c9cd0d89d".to_string(),
+            function: HashAlgorithm::Sha1,
+        };
+        let actual = "da39a3ee5e6b4b0d3255bfef95601890afd80709".to_string();
+
+        match check_digest(&hash, actual.clone()) {
+            Err(DownloadError::HashMismatch {
+                expected,
+                actual: got,
+            }) => {
+                assert_eq!(expected, hash.hash);
+                assert_eq!(got, actual);
+            }
+            result => panic!("expected a hash mismatch, got {:?}", result),
+        }
+    }
+}